@@ -0,0 +1,58 @@
+// `cargo bench --bench blend`: throughput of the blenders on a 4K frame worth
+// of pixels. The crate is a binary, so the module is compiled in directly
+use std::{hint::black_box, time::Instant};
+
+// Checked with `--all-targets` the module's tests are compiled in too, without
+// a harness to run them
+#[cfg_attr(test, allow(dead_code, unused_imports))]
+#[path = "../src/blend.rs"]
+mod blend;
+
+const WIDTH: usize = 3840;
+const HEIGHT: usize = 2160;
+const ROUNDS: u32 = 20;
+
+// Best of `ROUNDS` runs of `blend` over every row, in megapixels per second
+fn measure(name: &str, pixels: &mut [u32], mut blend: impl FnMut(&mut [u32])) {
+    let mut best = f64::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        for row in pixels.chunks_exact_mut(WIDTH) {
+            blend(black_box(row));
+        }
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    let megapixels = (WIDTH * HEIGHT) as f64 / 1e6;
+    println!("{name:<18} {:>8.1} Mpx/s", megapixels / best);
+}
+
+fn main() {
+    // A mix of opaque, translucent and transparent destinations, and coverage
+    // like an antialiased brush: mostly full with a soft edge
+    let mut pixels: Vec<u32> = (0..WIDTH * HEIGHT)
+        .map(|index| {
+            let alpha = [0xFF, 0x80, 0x00][index % 3] << 24;
+            alpha | (index as u32).wrapping_mul(2654435761) & 0x00FFFFFF
+        })
+        .collect();
+    let coverage: Vec<f32> = (0..WIDTH)
+        .map(|column| (column % 64) as f32 / 48.0)
+        .collect();
+    let layer: Vec<u32> = pixels.iter().map(|pixel| pixel.rotate_left(8)).collect();
+
+    measure("source over", &mut pixels, |row| {
+        blend::source_over(row, 0x003b82f6, &coverage)
+    });
+    measure("erase", &mut pixels, |row| blend::erase(row, &coverage));
+    measure("multiply", &mut pixels, |row| {
+        blend::multiply(row, 0x00808080, &coverage)
+    });
+    measure("composite", &mut pixels, |row| {
+        blend::composite(row, &layer[..WIDTH])
+    });
+    measure("source over pixel", &mut pixels, |row| {
+        for pixel in row {
+            *pixel = blend::source_over_pixel(*pixel, 0x00ef4444, 0.5);
+        }
+    });
+}
@@ -0,0 +1,319 @@
+// Animated GIF and APNG encoding. Frames are full 0xAARRGGBB images of the
+// same size, each shown for its `delay`, and the animation loops forever
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    time::Duration,
+};
+
+use weezl::{BitOrder, encode::Encoder};
+
+pub struct Frame {
+    pub pixels: Vec<u32>,
+    pub delay: Duration,
+}
+
+// GIF has room for 256 colors, the first one is kept for transparency
+const GIF_COLORS: usize = 255;
+const GIF_TRANSPARENT: u8 = 0;
+
+// GIF sub-blocks hold at most this many bytes
+const GIF_BLOCK: usize = 255;
+
+// The one palette all frames share: the most common opaque colors of
+// `palette_sources`, usually the first and last frame, with everything else
+// mapped to the nearest of those
+pub fn write_gif(
+    mut out: impl Write,
+    width: u32,
+    height: u32,
+    palette_sources: &[&[u32]],
+    frames: impl Iterator<Item = Frame>,
+) -> io::Result<()> {
+    let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(io::Error::other("too large for a GIF"));
+    };
+
+    let mut palette = Palette::new(palette_sources);
+
+    out.write_all(b"GIF89a")?;
+    out.write_all(&gif_width.to_le_bytes())?;
+    out.write_all(&gif_height.to_le_bytes())?;
+    // Global color table of 256 entries, background color 0, square pixels
+    out.write_all(&[0xF7, 0, 0])?;
+    let mut table = [0u8; 256 * 3];
+    for (index, &color) in palette.colors.iter().enumerate() {
+        let entry = &mut table[(index + 1) * 3..(index + 2) * 3];
+        entry.copy_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+    }
+    out.write_all(&table)?;
+    // Loop forever
+    out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+    // Delays are in hundredths of a second, rounded so they don't drift
+    let mut elapsed = Duration::ZERO;
+    let mut written = 0;
+    for frame in frames {
+        elapsed += frame.delay;
+        let total = (elapsed.as_secs_f64() * 100.0).round() as u64;
+        let delay = (total - written).min(u16::MAX as u64) as u16;
+        written = total;
+
+        // Graphic control: restore to the background between frames so
+        // transparent pixels don't show the previous one
+        out.write_all(&[0x21, 0xF9, 0x04, 0x09])?;
+        out.write_all(&delay.to_le_bytes())?;
+        out.write_all(&[GIF_TRANSPARENT, 0])?;
+
+        out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        out.write_all(&gif_width.to_le_bytes())?;
+        out.write_all(&gif_height.to_le_bytes())?;
+        out.write_all(&[0])?;
+
+        let indices: Vec<u8> = frame
+            .pixels
+            .iter()
+            .map(|&pixel| palette.index(pixel))
+            .collect();
+        let data = Encoder::new(BitOrder::Lsb, 8)
+            .encode(&indices)
+            .map_err(io::Error::other)?;
+        out.write_all(&[8])?;
+        for block in data.chunks(GIF_BLOCK) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0])?;
+    }
+
+    out.write_all(&[0x3B])?;
+    out.flush()
+}
+
+pub fn write_apng(
+    out: impl Write,
+    width: u32,
+    height: u32,
+    frame_count: u32,
+    frames: impl Iterator<Item = Frame>,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frame_count, 0)?;
+
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        let delay = frame.delay.as_millis().min(u16::MAX as u128) as u16;
+        writer.set_frame_delay(delay, 1000)?;
+        writer.write_image_data(&crate::export::to_rgba(&frame.pixels))?;
+    }
+    writer.finish()
+}
+
+struct Palette {
+    colors: Vec<u32>,          // 0xRRGGBB, at index + 1 in the color table
+    indices: HashMap<u32, u8>, // Pixels seen so far and where they went
+}
+
+impl Palette {
+    fn new(sources: &[&[u32]]) -> Self {
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for &pixel in sources.iter().flat_map(|source| source.iter()) {
+            if is_opaque(pixel) {
+                *counts.entry(pixel & 0xFFFFFF).or_default() += 1;
+            }
+        }
+        let mut colors: Vec<(u32, usize)> = counts.into_iter().collect();
+        // Ties broken by color so the output doesn't depend on hashing
+        colors.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        colors.truncate(GIF_COLORS);
+
+        Self {
+            colors: colors.into_iter().map(|(color, _)| color).collect(),
+            indices: HashMap::new(),
+        }
+    }
+
+    fn index(&mut self, pixel: u32) -> u8 {
+        if !is_opaque(pixel) || self.colors.is_empty() {
+            return GIF_TRANSPARENT;
+        }
+        let color = pixel & 0xFFFFFF;
+        *self.indices.entry(color).or_insert_with(|| {
+            let nearest = self
+                .colors
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &candidate)| distance(color, candidate))
+                .map_or(0, |(index, _)| index);
+            nearest as u8 + 1
+        })
+    }
+}
+
+// Half transparent or more counts as transparent, GIF has nothing in between
+fn is_opaque(pixel: u32) -> bool {
+    pixel >> 24 >= 0x80
+}
+
+fn distance(a: u32, b: u32) -> u32 {
+    [16, 8, 0]
+        .iter()
+        .map(|&shift| {
+            let difference = ((a >> shift) & 0xFF).abs_diff((b >> shift) & 0xFF);
+            difference * difference
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use weezl::decode::Decoder;
+
+    use super::*;
+
+    const RED: u32 = 0xFFEF4444;
+    const BLUE: u32 = 0xFF3B82F6;
+
+    // Three 2x2 frames whose top left pixel is always transparent
+    fn frames(fps: u32) -> Vec<Frame> {
+        [
+            [0, RED, RED, RED],
+            [0, RED, BLUE, RED],
+            [0, BLUE, BLUE, BLUE],
+        ]
+        .into_iter()
+        .map(|pixels| Frame {
+            pixels: pixels.to_vec(),
+            delay: Duration::from_secs_f32(1.0 / fps as f32),
+        })
+        .collect()
+    }
+
+    struct GifFrame {
+        delay: u16,
+        transparent: Option<u8>,
+        indices: Vec<u8>,
+    }
+
+    // The color table and frames of a GIF, walking its blocks
+    fn parse_gif(bytes: &[u8]) -> (Vec<u8>, Vec<GifFrame>) {
+        fn sub_blocks(bytes: &[u8], position: &mut usize) -> Vec<u8> {
+            let mut data = Vec::new();
+            loop {
+                let length = bytes[*position] as usize;
+                *position += 1;
+                if length == 0 {
+                    return data;
+                }
+                data.extend_from_slice(&bytes[*position..*position + length]);
+                *position += length;
+            }
+        }
+
+        assert_eq!(&bytes[..6], b"GIF89a");
+        assert_eq!(bytes[10] & 0x80, 0x80, "no global color table");
+        let table_length = 3 << ((bytes[10] & 0x07) + 1);
+        let table = bytes[13..13 + table_length].to_vec();
+        let mut position = 13 + table_length;
+
+        let mut frames = Vec::new();
+        let mut control = None;
+        loop {
+            match bytes[position] {
+                0x21 => {
+                    let label = bytes[position + 1];
+                    position += 2;
+                    let data = sub_blocks(bytes, &mut position);
+                    if label == 0xF9 {
+                        let delay = u16::from_le_bytes([data[1], data[2]]);
+                        control = Some((delay, (data[0] & 1 == 1).then_some(data[3])));
+                    }
+                }
+                0x2C => {
+                    assert_eq!(
+                        bytes[position + 9] & 0x80,
+                        0,
+                        "unexpected local color table"
+                    );
+                    let minimum_code_size = bytes[position + 10];
+                    position += 11;
+                    let data = sub_blocks(bytes, &mut position);
+                    let indices = Decoder::new(BitOrder::Lsb, minimum_code_size)
+                        .decode(&data)
+                        .unwrap();
+                    let (delay, transparent) = control.take().expect("no graphic control");
+                    frames.push(GifFrame {
+                        delay,
+                        transparent,
+                        indices,
+                    });
+                }
+                0x3B => return (table, frames),
+                block => panic!("unexpected block {block:#x}"),
+            }
+        }
+    }
+
+    #[test]
+    fn writes_gifs() {
+        let frames = frames(10);
+        let (first, last) = (frames[0].pixels.clone(), frames[2].pixels.clone());
+        let mut bytes = Vec::new();
+        write_gif(&mut bytes, 2, 2, &[&first, &last], frames.into_iter()).unwrap();
+
+        let (table, frames) = parse_gif(&bytes);
+        assert_eq!(frames.len(), 3);
+        for frame in &frames {
+            assert_eq!(frame.delay, 10);
+            assert_eq!(frame.transparent, Some(GIF_TRANSPARENT));
+            assert_eq!(frame.indices.len(), 4);
+            assert_eq!(frame.indices[0], GIF_TRANSPARENT);
+        }
+        let color = |index: u8| {
+            let entry = &table[index as usize * 3..index as usize * 3 + 3];
+            u32::from_be_bytes([0xFF, entry[0], entry[1], entry[2]])
+        };
+        assert_eq!(color(frames[1].indices[1]), RED);
+        assert_eq!(color(frames[1].indices[2]), BLUE);
+    }
+
+    #[test]
+    fn gif_delays_add_up() {
+        // A thirtieth of a second isn't a whole number of hundredths
+        let mut bytes = Vec::new();
+        let frames = frames(30);
+        let first = frames[0].pixels.clone();
+        write_gif(&mut bytes, 2, 2, &[&first], frames.into_iter()).unwrap();
+
+        let delays: Vec<u16> = parse_gif(&bytes)
+            .1
+            .iter()
+            .map(|frame| frame.delay)
+            .collect();
+        assert!(delays.iter().all(|&delay| delay == 3 || delay == 4));
+        assert_eq!(delays.iter().sum::<u16>(), 10);
+    }
+
+    #[test]
+    fn writes_apngs() {
+        let mut bytes = Vec::new();
+        write_apng(&mut bytes, 2, 2, 3, frames(10).into_iter()).unwrap();
+
+        let mut reader = png::Decoder::new(Cursor::new(&bytes)).read_info().unwrap();
+        let control = reader.info().animation_control.unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (3, 0));
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap()];
+        for _ in 0..3 {
+            reader.next_frame(&mut buffer).unwrap();
+            let frame = reader.info().frame_control.unwrap();
+            assert_eq!((frame.delay_num, frame.delay_den), (100, 1000));
+            // Straight RGBA, the transparent pixel first
+            assert_eq!(buffer[3], 0);
+            assert_eq!(buffer[7], 0xFF);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// What is shown under the drawing. It is never part of the document layer, so
+// clearing and erasing leave it alone, and patterns are laid out in world
+// units so they pan and zoom with the strokes
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blend, image::Image};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Solid,
+    Grid,
+    Dots,
+    Lines, // Ruled paper
+    Transparent,
+}
+
+impl Pattern {
+    pub fn next(self) -> Self {
+        match self {
+            Pattern::Solid => Pattern::Grid,
+            Pattern::Grid => Pattern::Dots,
+            Pattern::Dots => Pattern::Lines,
+            Pattern::Lines => Pattern::Transparent,
+            Pattern::Transparent => Pattern::Solid,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Pattern::Solid => 0,
+            Pattern::Grid => 1,
+            Pattern::Dots => 2,
+            Pattern::Lines => 3,
+            Pattern::Transparent => 4,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Pattern::Solid,
+            1 => Pattern::Grid,
+            2 => Pattern::Dots,
+            3 => Pattern::Lines,
+            4 => Pattern::Transparent,
+            _ => return None,
+        })
+    }
+}
+
+// Colors Shift+B cycles through
+pub const COLORS: [u32; 4] = [
+    0x000a0a0a, // Black
+    0x00fafafa, // White
+    0x001e293b, // Slate
+    0x00f5f0e1, // Paper
+];
+
+pub const MIN_SPACING: f32 = 5.0;
+pub const MAX_SPACING: f32 = 500.0;
+
+// Below this many pixels between lines the pattern turns into noise, so it is
+// left out until zooming in again
+const MIN_VISIBLE_STEP: f32 = 4.0;
+
+// Size of the squares showing a transparent background on screen
+const CHECKER_SIZE: i32 = 8;
+
+#[derive(Clone)]
+pub struct Background {
+    pub pattern: Pattern,
+    pub color: u32,
+    pub spacing: f32, // World units between grid lines, dots or rules
+    // Shown on top of the pattern with its top-left corner at the world origin,
+    // one world unit per image pixel. A frozen screenshot in freeze mode
+    pub image: Option<Arc<Image>>,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self {
+            pattern: Pattern::Solid,
+            color: COLORS[0],
+            spacing: 40.0,
+            image: None,
+        }
+    }
+}
+
+impl Background {
+    // A faint line color that shows on both dark and light backgrounds
+    fn line_color(&self) -> u32 {
+        let luminance = (((self.color >> 16) & 0xFF) * 3
+            + ((self.color >> 8) & 0xFF) * 6
+            + (self.color & 0xFF))
+            / 10;
+        let contrast = if luminance < 128 { 0xFFFFFF } else { 0x000000 };
+        blend::source_over_pixel(self.color | 0xFF000000, contrast, 0.15)
+    }
+
+    // Fill `rect` (x, y, width, height) of a `target_width` pixels wide buffer,
+    // target pixel (0, 0) being canvas pixel `origin` at `zoom`. A transparent
+    // background is filled with alpha 0, or with a checkerboard for `preview`
+    pub fn render(
+        &self,
+        target: &mut [u32],
+        target_width: u32,
+        (x, y, width, height): (u32, u32, u32, u32),
+        origin: (i32, i32),
+        zoom: f32,
+        preview: bool,
+    ) {
+        let color = self.color | 0xFF000000;
+        let line_color = self.line_color();
+        let step = self.spacing * zoom;
+        let has_lines = step >= MIN_VISIBLE_STEP;
+
+        // Whether a multiple of `step` falls inside canvas pixel `value`
+        let on_line = |value: i32| {
+            has_lines && (value as f32 / step).floor() != ((value + 1) as f32 / step).floor()
+        };
+        // Dots are two pixels wide so they don't get lost
+        let on_dot = |value: i32| on_line(value) || on_line(value - 1);
+
+        let columns: Vec<i32> = (x..x + width)
+            .map(|column| column as i32 + origin.0)
+            .collect();
+        let dot_columns: Vec<bool> = if self.pattern == Pattern::Dots {
+            columns.iter().map(|&column| on_dot(column)).collect()
+        } else {
+            Vec::new()
+        };
+        let line_columns: Vec<bool> = if self.pattern == Pattern::Grid {
+            columns.iter().map(|&column| on_line(column)).collect()
+        } else {
+            Vec::new()
+        };
+
+        for row in y..y + height {
+            let canvas_y = row as i32 + origin.1;
+            let start = (row * target_width + x) as usize;
+            let pixels = &mut target[start..start + width as usize];
+
+            match self.pattern {
+                Pattern::Solid => pixels.fill(color),
+                Pattern::Lines => pixels.fill(if on_line(canvas_y) { line_color } else { color }),
+                Pattern::Grid if on_line(canvas_y) => pixels.fill(line_color),
+                Pattern::Grid => {
+                    for (pixel, &is_line) in pixels.iter_mut().zip(&line_columns) {
+                        *pixel = if is_line { line_color } else { color };
+                    }
+                }
+                Pattern::Dots if on_dot(canvas_y) => {
+                    for (pixel, &is_dot) in pixels.iter_mut().zip(&dot_columns) {
+                        *pixel = if is_dot { line_color } else { color };
+                    }
+                }
+                Pattern::Dots => pixels.fill(color),
+                Pattern::Transparent if preview => {
+                    let checker_row = canvas_y.div_euclid(CHECKER_SIZE);
+                    for (pixel, &column) in pixels.iter_mut().zip(&columns) {
+                        let is_dark =
+                            (checker_row + column.div_euclid(CHECKER_SIZE)).rem_euclid(2) == 0;
+                        *pixel = if is_dark { 0xFF1A1A1A } else { 0xFF262626 };
+                    }
+                }
+                Pattern::Transparent => pixels.fill(0),
+            }
+        }
+
+        if let Some(image) = &self.image {
+            draw_image(
+                image,
+                target,
+                target_width,
+                (x, y, width, height),
+                origin,
+                zoom,
+            );
+        }
+    }
+
+    // The nearest grid intersection to a world point, `None` unless the
+    // pattern has intersections to snap to
+    pub fn snap(&self, (x, y): (f32, f32)) -> Option<(f32, f32)> {
+        matches!(self.pattern, Pattern::Grid | Pattern::Dots).then(|| {
+            (
+                (x / self.spacing).round() * self.spacing,
+                (y / self.spacing).round() * self.spacing,
+            )
+        })
+    }
+
+    // The color vector exports fill the page with, `None` when transparent
+    pub fn fill_color(&self) -> Option<u32> {
+        (self.pattern != Pattern::Transparent).then_some(self.color)
+    }
+}
+
+// Nearest neighbour sampling of an image spanning world (0, 0) to (width,
+// height), restricted to `rect` of the target
+fn draw_image(
+    image: &Image,
+    target: &mut [u32],
+    target_width: u32,
+    (x, y, width, height): (u32, u32, u32, u32),
+    origin: (i32, i32),
+    zoom: f32,
+) {
+    // Image pixel under a canvas pixel, if any
+    let source = |canvas: i32, size: u32| {
+        let source = ((canvas as f32 + 0.5) / zoom).floor();
+        (source >= 0.0 && source < size as f32).then_some(source as u32)
+    };
+    let columns: Vec<Option<u32>> = (x..x + width)
+        .map(|column| source(column as i32 + origin.0, image.width))
+        .collect();
+
+    for row in y..y + height {
+        let Some(source_y) = source(row as i32 + origin.1, image.height) else {
+            continue;
+        };
+        let source_row = &image.pixels[(source_y * image.width) as usize..];
+        let start = (row * target_width + x) as usize;
+        for (pixel, column) in target[start..start + width as usize]
+            .iter_mut()
+            .zip(&columns)
+        {
+            let Some(source_x) = column else {
+                continue;
+            };
+            let color = source_row[*source_x as usize];
+            *pixel = match color >> 24 {
+                0 => *pixel,
+                0xFF => color,
+                alpha => blend::source_over_pixel(*pixel, color, alpha as f32 / 255.0),
+            };
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// Blending a run of pixels with one color, each pixel weighted by its own
+// coverage (0 leaves it alone, 1 replaces it). On x86_64 CPUs with AVX2 eight
+// pixels are blended at a time, everywhere else one by one. Both paths do the
+// same f32 operations in the same order, so they give bit-identical results
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+// Source over: `color` painted on top. Pixels are straight (non-premultiplied)
+// 0xAARRGGBB, opaque pixels stay opaque and transparent ones pick up `color`
+// with the coverage as their alpha
+pub fn source_over(pixels: &mut [u32], color: u32, coverage: &[f32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was just checked
+        unsafe { avx2::source_over(pixels, color, coverage) };
+        return;
+    }
+    scalar::source_over(pixels, color, coverage);
+}
+
+// Erase: fades pixels out to transparent, leaving their color as it is
+pub fn erase(pixels: &mut [u32], coverage: &[f32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was just checked
+        unsafe { avx2::erase(pixels, coverage) };
+        return;
+    }
+    scalar::erase(pixels, coverage);
+}
+
+// Multiply: darkens pixels by `color` like a highlighter, the result is opaque
+#[allow(dead_code)] // For a highlighter brush
+pub fn multiply(pixels: &mut [u32], color: u32, coverage: &[f32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was just checked
+        unsafe { avx2::multiply(pixels, color, coverage) };
+        return;
+    }
+    scalar::multiply(pixels, color, coverage);
+}
+
+// One pixel source over, for callers whose color changes from pixel to pixel
+pub fn source_over_pixel(background: u32, foreground: u32, alpha: f32) -> u32 {
+    scalar::source_over_one(background, foreground, alpha.clamp(0.0, 1.0))
+}
+
+// A layer put on top of `pixels`, each layer pixel weighted by its own alpha.
+// Layers are mostly fully transparent or fully opaque, which skip the math
+pub fn composite(pixels: &mut [u32], layer: &[u32]) {
+    for (pixel, &source) in pixels.iter_mut().zip(layer) {
+        match source >> 24 {
+            0 => {}
+            0xFF => *pixel = source,
+            alpha => *pixel = scalar::source_over_one(*pixel, source, alpha as f32 / 255.0),
+        }
+    }
+}
+
+// The reference implementation, also used for whatever is left over after the
+// vector loops
+mod scalar {
+    pub fn source_over(pixels: &mut [u32], color: u32, coverage: &[f32]) {
+        for (pixel, &alpha) in pixels.iter_mut().zip(coverage) {
+            if alpha > 0.0 {
+                *pixel = source_over_one(*pixel, color, alpha.min(1.0));
+            }
+        }
+    }
+
+    pub fn source_over_one(background: u32, foreground: u32, alpha: f32) -> u32 {
+        let inv_alpha = 1.0 - alpha;
+        if background >> 24 == 0xFF {
+            // Opaque underneath, the result is a plain mix
+            let channel = |shift: u32| {
+                let bg = ((background >> shift) & 0xFF) as f32;
+                let fg = ((foreground >> shift) & 0xFF) as f32;
+                (fg * alpha + bg * inv_alpha) as u32
+            };
+            return 0xFF000000 | (channel(16) << 16) | (channel(8) << 8) | channel(0);
+        }
+
+        // How much of the background is left, and the alpha of the result
+        let weight = (background >> 24) as f32 / 255.0 * inv_alpha;
+        let out_alpha = alpha + weight;
+        let channel = |shift: u32| {
+            let bg = ((background >> shift) & 0xFF) as f32;
+            let fg = ((foreground >> shift) & 0xFF) as f32;
+            (((fg * alpha + bg * weight) / out_alpha + 0.5) as u32).min(255)
+        };
+        (((out_alpha * 255.0 + 0.5) as u32) << 24)
+            | (channel(16) << 16)
+            | (channel(8) << 8)
+            | channel(0)
+    }
+
+    pub fn erase(pixels: &mut [u32], coverage: &[f32]) {
+        for (pixel, &alpha) in pixels.iter_mut().zip(coverage) {
+            if alpha > 0.0 {
+                let inv_alpha = 1.0 - alpha.min(1.0);
+                let a = ((*pixel >> 24) as f32 * inv_alpha) as u32;
+                *pixel = (*pixel & 0x00FFFFFF) | (a << 24);
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn multiply(pixels: &mut [u32], color: u32, coverage: &[f32]) {
+        for (pixel, &alpha) in pixels.iter_mut().zip(coverage) {
+            if alpha > 0.0 {
+                let alpha = alpha.min(1.0);
+                let inv_alpha = 1.0 - alpha;
+                let channel = |shift: u32| {
+                    let bg = ((*pixel >> shift) & 0xFF) as f32;
+                    let fg = ((color >> shift) & 0xFF) as f32;
+                    (bg * fg / 255.0 * alpha + bg * inv_alpha) as u32
+                };
+                *pixel = 0xFF000000 | (channel(16) << 16) | (channel(8) << 8) | channel(0);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::*;
+
+    const LANES: usize = 8;
+
+    // Channel `shift` of eight pixels as floats
+    #[target_feature(enable = "avx2")]
+    fn channel(pixels: __m256i, shift: i32) -> __m256 {
+        let shifted = match shift {
+            24 => _mm256_srli_epi32::<24>(pixels),
+            16 => _mm256_srli_epi32::<16>(pixels),
+            8 => _mm256_srli_epi32::<8>(pixels),
+            _ => pixels,
+        };
+        _mm256_cvtepi32_ps(_mm256_and_si256(shifted, _mm256_set1_epi32(0xFF)))
+    }
+
+    // Truncated like `as u32` and put back into channel `shift`
+    #[target_feature(enable = "avx2")]
+    fn pack(value: __m256, shift: i32) -> __m256i {
+        let value = _mm256_cvttps_epi32(value);
+        match shift {
+            24 => _mm256_slli_epi32::<24>(value),
+            16 => _mm256_slli_epi32::<16>(value),
+            8 => _mm256_slli_epi32::<8>(value),
+            _ => value,
+        }
+    }
+
+    // Like `pack`, limited to 0..=255 the way `as u32` followed by `min(255)` is
+    #[target_feature(enable = "avx2")]
+    fn pack_clamped(value: __m256, shift: i32) -> __m256i {
+        let value = _mm256_cvttps_epi32(value);
+        let value = _mm256_max_epi32(
+            _mm256_min_epi32(value, _mm256_set1_epi32(0xFF)),
+            _mm256_setzero_si256(),
+        );
+        match shift {
+            16 => _mm256_slli_epi32::<16>(value),
+            8 => _mm256_slli_epi32::<8>(value),
+            _ => value,
+        }
+    }
+
+    // Run `blend` over every full group of eight pixels, keeping pixels with no
+    // coverage as they were, and return how many pixels were done
+    #[target_feature(enable = "avx2")]
+    fn for_each_lane_group(
+        pixels: &mut [u32],
+        coverage: &[f32],
+        blend: impl Fn(__m256i, __m256, __m256) -> __m256i,
+    ) -> usize {
+        let count = pixels.len().min(coverage.len()) / LANES * LANES;
+        let zero = _mm256_setzero_ps();
+        let one = _mm256_set1_ps(1.0);
+
+        for (pixels, coverage) in pixels[..count]
+            .chunks_exact_mut(LANES)
+            .zip(coverage[..count].chunks_exact(LANES))
+        {
+            // SAFETY: both chunks hold exactly eight 32 bit values, and the
+            // unaligned load and store instructions are used
+            unsafe {
+                let destination = _mm256_loadu_si256(pixels.as_ptr() as *const __m256i);
+                let raw_alpha = _mm256_loadu_ps(coverage.as_ptr());
+                let alpha = _mm256_min_ps(raw_alpha, one);
+                let inv_alpha = _mm256_sub_ps(one, alpha);
+
+                let blended = blend(destination, alpha, inv_alpha);
+                let untouched = _mm256_castps_si256(_mm256_cmp_ps::<_CMP_LE_OQ>(raw_alpha, zero));
+                let result = _mm256_blendv_epi8(blended, destination, untouched);
+                _mm256_storeu_si256(pixels.as_mut_ptr() as *mut __m256i, result);
+            }
+        }
+        count
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub fn source_over(pixels: &mut [u32], color: u32, coverage: &[f32]) {
+        let foreground = _mm256_set1_epi32(color as i32);
+        let max = _mm256_set1_ps(255.0);
+        let half = _mm256_set1_ps(0.5);
+        let done = for_each_lane_group(pixels, coverage, |destination, alpha, inv_alpha| {
+            // Both the opaque and the translucent formula, picked per pixel below
+            let destination_alpha = channel(destination, 24);
+            let weight = _mm256_mul_ps(_mm256_div_ps(destination_alpha, max), inv_alpha);
+            let out_alpha = _mm256_add_ps(alpha, weight);
+
+            let mut opaque = _mm256_set1_epi32(0xFF000000u32 as i32);
+            let mut translucent = pack(_mm256_add_ps(_mm256_mul_ps(out_alpha, max), half), 24);
+            for shift in [16, 8, 0] {
+                let background = channel(destination, shift);
+                let weighted = _mm256_mul_ps(channel(foreground, shift), alpha);
+
+                let value = _mm256_add_ps(weighted, _mm256_mul_ps(background, inv_alpha));
+                opaque = _mm256_or_si256(opaque, pack(value, shift));
+
+                let value = _mm256_add_ps(
+                    _mm256_div_ps(
+                        _mm256_add_ps(weighted, _mm256_mul_ps(background, weight)),
+                        out_alpha,
+                    ),
+                    half,
+                );
+                translucent = _mm256_or_si256(translucent, pack_clamped(value, shift));
+            }
+
+            let is_opaque =
+                _mm256_castps_si256(_mm256_cmp_ps::<_CMP_EQ_OQ>(destination_alpha, max));
+            _mm256_blendv_epi8(translucent, opaque, is_opaque)
+        });
+        scalar::source_over(&mut pixels[done..], color, &coverage[done..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub fn erase(pixels: &mut [u32], coverage: &[f32]) {
+        let done = for_each_lane_group(pixels, coverage, |destination, _, inv_alpha| {
+            let color = _mm256_and_si256(destination, _mm256_set1_epi32(0x00FFFFFF));
+            let alpha = _mm256_mul_ps(channel(destination, 24), inv_alpha);
+            _mm256_or_si256(color, pack(alpha, 24))
+        });
+        scalar::erase(&mut pixels[done..], &coverage[done..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub fn multiply(pixels: &mut [u32], color: u32, coverage: &[f32]) {
+        let foreground = _mm256_set1_epi32(color as i32);
+        let max = _mm256_set1_ps(255.0);
+        let done = for_each_lane_group(pixels, coverage, |destination, alpha, inv_alpha| {
+            let mut result = _mm256_set1_epi32(0xFF000000u32 as i32);
+            for shift in [16, 8, 0] {
+                let background = channel(destination, shift);
+                let product =
+                    _mm256_div_ps(_mm256_mul_ps(background, channel(foreground, shift)), max);
+                let value = _mm256_add_ps(
+                    _mm256_mul_ps(product, alpha),
+                    _mm256_mul_ps(background, inv_alpha),
+                );
+                result = _mm256_or_si256(result, pack(value, shift));
+            }
+            result
+        });
+        scalar::multiply(&mut pixels[done..], color, &coverage[done..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift32, enough to make up pixels and coverage reproducibly
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        // Opaque, transparent and translucent destinations
+        fn pixel(&mut self) -> u32 {
+            let color = self.next() & 0x00FFFFFF;
+            match self.next() % 4 {
+                0 => color | 0xFF000000,
+                1 => color,
+                _ => color | (self.next() << 24),
+            }
+        }
+
+        // Including the edge cases the blenders clamp or skip
+        fn coverage(&mut self) -> f32 {
+            match self.next() % 6 {
+                0 => 0.0,
+                1 => 1.0,
+                2 => 1.0 + (self.next() % 1000) as f32 / 100.0,
+                3 => -((self.next() % 1000) as f32 / 100.0),
+                _ => (self.next() % 10_000) as f32 / 10_000.0,
+            }
+        }
+    }
+
+    // Run `vector` and `reference` on the same random runs, every length from
+    // empty to a few lane groups plus a remainder
+    fn assert_agree(
+        vector: impl Fn(&mut [u32], u32, &[f32]),
+        reference: impl Fn(&mut [u32], u32, &[f32]),
+    ) {
+        let mut random = Random(0x2545F491);
+        for round in 0..2000 {
+            let len = round % 43;
+            let color = random.next();
+            let pixels: Vec<u32> = (0..len).map(|_| random.pixel()).collect();
+            let coverage: Vec<f32> = (0..len).map(|_| random.coverage()).collect();
+
+            let mut expected = pixels.clone();
+            reference(&mut expected, color, &coverage);
+            let mut actual = pixels.clone();
+            vector(&mut actual, color, &coverage);
+            assert_eq!(
+                actual, expected,
+                "pixels {pixels:08x?} color {color:08x} coverage {coverage:?}"
+            );
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2_matches_scalar() {
+        if !is_x86_feature_detected!("avx2") {
+            eprintln!("skipped, this CPU has no AVX2");
+            return;
+        }
+
+        // SAFETY: AVX2 support was just checked
+        assert_agree(
+            |pixels, color, coverage| unsafe { avx2::source_over(pixels, color, coverage) },
+            scalar::source_over,
+        );
+        assert_agree(
+            |pixels, _, coverage| unsafe { avx2::erase(pixels, coverage) },
+            |pixels, _, coverage| scalar::erase(pixels, coverage),
+        );
+        assert_agree(
+            |pixels, color, coverage| unsafe { avx2::multiply(pixels, color, coverage) },
+            scalar::multiply,
+        );
+    }
+}
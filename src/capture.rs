@@ -0,0 +1,186 @@
+// Grabbing a still of the screen for freeze mode, so windows moving underneath
+// can't pull the annotated content out from under the drawing. X11 is read
+// directly with GetImage, Wayland goes through `grim` (wlr-screencopy), and a
+// PNG file can stand in for either
+use std::{error::Error, fmt, fs, path::Path, process::Command};
+
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder},
+};
+
+use crate::image::Image;
+
+pub enum Screenshot {
+    Screen(Image), // Every monitor, laid out the way the display server has them
+    File(Image),   // Used as is, whichever monitor the window ends up on
+}
+
+#[derive(Debug)]
+pub struct CaptureError(String);
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for CaptureError {}
+
+impl Screenshot {
+    // The part that is under a monitor at `position` (in screen coordinates)
+    // of `width` x `height` pixels
+    pub fn for_monitor(self, position: (i32, i32), (width, height): (u32, u32)) -> Image {
+        let image = match self {
+            Screenshot::File(image) => return image,
+            Screenshot::Screen(image) => image,
+        };
+
+        let left = position.0.clamp(0, image.width as i32) as u32;
+        let top = position.1.clamp(0, image.height as i32) as u32;
+        let right = (position.0 + width as i32).clamp(left as i32, image.width as i32) as u32;
+        let bottom = (position.1 + height as i32).clamp(top as i32, image.height as i32) as u32;
+        if (left, top, right, bottom) == (0, 0, image.width, image.height) {
+            return image;
+        }
+
+        let pixels = (top..bottom)
+            .flat_map(|row| {
+                let start = (row * image.width) as usize;
+                image.pixels[start + left as usize..start + right as usize].iter()
+            })
+            .copied()
+            .collect();
+        Image {
+            width: right - left,
+            height: bottom - top,
+            pixels,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Screenshot, CaptureError> {
+    let bytes = fs::read(path).map_err(|err| CaptureError(err.to_string()))?;
+    let image = Image::decode_png(&bytes).map_err(|err| CaptureError(err.to_string()))?;
+    Ok(Screenshot::File(image))
+}
+
+// The live screen, Wayland first since X11 clients there only see XWayland
+pub fn capture() -> Result<Screenshot, CaptureError> {
+    let image = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        capture_wayland()?
+    } else {
+        capture_x11()?
+    };
+    Ok(Screenshot::Screen(image))
+}
+
+fn capture_wayland() -> Result<Image, CaptureError> {
+    let output = Command::new("grim")
+        .arg("-")
+        .output()
+        .map_err(|err| CaptureError(format!("could not run grim: {err}")))?;
+    if !output.status.success() {
+        return Err(CaptureError(format!(
+            "grim failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Image::decode_png(&output.stdout).map_err(|err| CaptureError(err.to_string()))
+}
+
+fn capture_x11() -> Result<Image, CaptureError> {
+    let x11_error = |err: &dyn Error| CaptureError(format!("X11: {err}"));
+
+    let (connection, screen) = x11rb::connect(None).map_err(|err| x11_error(&err))?;
+    let setup = connection.setup();
+    let root = &setup.roots[screen];
+    let (width, height) = (root.width_in_pixels, root.height_in_pixels);
+
+    let reply = connection
+        .get_image(ImageFormat::Z_PIXMAP, root.root, 0, 0, width, height, !0)
+        .map_err(|err| x11_error(&err))?
+        .reply()
+        .map_err(|err| x11_error(&err))?;
+
+    // Only 24 bit color in 32 bit pixels is handled, which is what every
+    // desktop runs these days
+    let is_32_bit = setup
+        .pixmap_formats
+        .iter()
+        .any(|format| format.depth == reply.depth && format.bits_per_pixel == 32);
+    if !is_32_bit || reply.depth < 24 {
+        return Err(CaptureError(format!(
+            "X11: unsupported screen depth {}",
+            reply.depth
+        )));
+    }
+    let stride = width as usize * 4;
+    if reply.data.len() < stride * height as usize {
+        return Err(CaptureError("X11: short image".to_string()));
+    }
+
+    let pixels = reply
+        .data
+        .chunks_exact(4)
+        .take(width as usize * height as usize)
+        .map(|bytes| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            let pixel = if setup.image_byte_order == ImageOrder::LSB_FIRST {
+                u32::from_le_bytes(bytes)
+            } else {
+                u32::from_be_bytes(bytes)
+            };
+            pixel | 0xFF000000
+        })
+        .collect();
+
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::export;
+
+    // What `--freeze-from FILE` does: the PNG stands in for the live capture and
+    // is used as is, wherever the window ends up
+    #[test]
+    fn loads_a_png_in_place_of_the_screen() {
+        let pixels = [
+            0xFF3B82F6, 0xFFEF4444, 0xFF22C55E, 0xFF0A0A0A, 0xFFFFFFFF, 0xFF000000,
+        ];
+        let directory =
+            std::env::temp_dir().join(format!("drawonscreen-capture-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("screen.png");
+        export::save_png(&path, 3, 2, &export::to_rgba(&pixels)).unwrap();
+
+        let screenshot = load(&path);
+        fs::remove_dir_all(&directory).unwrap();
+        let image = screenshot.unwrap().for_monitor((1920, 0), (2560, 1440));
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.pixels, pixels);
+    }
+
+    #[test]
+    fn crops_the_screen_to_a_monitor() {
+        let screen = Image {
+            width: 4,
+            height: 2,
+            pixels: (0..8).map(|pixel| 0xFF000000 | pixel).collect(),
+        };
+        let image = Screenshot::Screen(screen).for_monitor((2, 0), (2, 2));
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(
+            image.pixels,
+            [0xFF000002, 0xFF000003, 0xFF000006, 0xFF000007]
+        );
+    }
+}
@@ -0,0 +1,510 @@
+// Command line parsing. Options configure the interactive window, subcommands
+// do their work without opening one
+use std::{ffi::OsString, net::IpAddr, path::PathBuf};
+
+use crate::{
+    config::{self, BackgroundSpec},
+    control::Request,
+    headless::{AnimationFormat, Format},
+};
+
+pub const USAGE: &str = "\
+Usage: drawonscreen_rust [OPTIONS] [FILE]
+       drawonscreen_rust render FILE.dos [--format png|svg] [--scale N] [-o OUT]
+       drawonscreen_rust timelapse FILE.dos [--format gif|apng] [--fps N]
+                         [--duration SECONDS] [--scale N] [-o OUT]
+       drawonscreen_rust toggle|clear
+
+FILE is a session (.dos) to continue or a PNG to draw on
+
+Options:
+  --config FILE        Read settings from FILE instead of
+                       $XDG_CONFIG_HOME/drawonscreen/config
+  --background VALUE   solid, grid, dots, lines, transparent, a #rrggbb color
+                       or a PNG file
+  --fullscreen         Cover the whole monitor
+  --monitor N          Open on monitor N, counted from 0
+  --output-dir DIR     Save exports to DIR instead of ~/Pictures
+  --load FILE          Same as passing FILE
+  --freeze             Draw over a screenshot of the monitor taken at launch
+  --freeze-from FILE   Like --freeze, with a PNG instead of a live capture
+  --record FILE        Record the input on the canvas to FILE
+  --replay FILE        Play back a recording, ignoring live input meanwhile
+  --replay-speed N     Replay N times as fast as it was recorded
+  --frames DIR|-       Write what the window shows as video frames, numbered
+                       PNGs in DIR or y4m on stdout
+  --frames-raw         Raw RGBA instead of y4m on stdout
+  --frames-fps N       Frames per second, 30 by default
+  --host PORT          Share the canvas with instances that join on PORT
+  --join HOST:PORT     Draw on the canvas shared by the instance at HOST:PORT
+  --serve PORT         Like --host, but those who join can only watch
+  --view HOST:PORT     Watch the canvas shared by the instance at HOST:PORT
+  --bind ADDRESS       Where --host and --serve listen, 127.0.0.1 by default.
+                       0.0.0.0 lets other machines join
+  --name NAME          Shown next to your strokes on a shared canvas, $USER
+                       by default
+  --http PORT          Serve the canvas at http://localhost:PORT/canvas.png
+                       and updates at /events, in builds with the http feature
+  --version            Print the version
+  --help               Print this message
+
+Only one instance runs at a time, starting another one brings the running
+window to the front. Instances sharing a canvas are the exception
+
+Subcommands:
+  toggle               Hide or show the running instance, or start one
+  clear                Clear the page of the running instance
+  render FILE.dos      Render the current page of a session without opening a
+                       window, next to it unless -o says where
+    --format png|svg   Output format, by default taken from the -o extension
+                       or else PNG
+    --scale N          Size relative to the window the session was saved in
+  timelapse FILE.dos   Animate the current page of a session being drawn,
+                       next to it unless -o says where
+    --format gif|apng  Output format, by default taken from the -o extension
+                       or else GIF
+    --fps N            Frames per second, 10 by default
+    --duration SECONDS How long the drawing takes to build up, 5 by default
+    --scale N          Size relative to the window the session was saved in";
+
+// Limits for `render --scale`. The renderer also refuses outputs too large to
+// hold in memory, whatever the scale
+const MIN_SCALE: f32 = 0.1;
+const MAX_SCALE: f32 = 16.0;
+
+// Limits for `--replay-speed`
+const MIN_REPLAY_SPEED: f32 = 0.1;
+const MAX_REPLAY_SPEED: f32 = 100.0;
+
+// Limits for `--frames-fps`
+const MIN_FRAMES_FPS: u32 = 1;
+const MAX_FRAMES_FPS: u32 = 120;
+
+// Limits for `timelapse`, GIF frame delays are counted in hundredths of a second
+const MIN_FPS: u32 = 1;
+const MAX_FPS: u32 = 50;
+const MIN_DURATION: f32 = 0.5;
+const MAX_DURATION: f32 = 600.0;
+
+// Only one of these at a time
+const SHARE_FLAGS: &str = "--host, --join, --serve or --view";
+
+pub enum Command {
+    Run(Box<Options>), // Boxed, it dwarfs the others
+    Render {
+        input: PathBuf,
+        output: Option<PathBuf>,
+        format: Format,
+        scale: f32,
+    },
+    Timelapse {
+        input: PathBuf,
+        output: Option<PathBuf>,
+        format: AnimationFormat,
+        fps: u32,
+        duration: f32,
+        scale: f32,
+    },
+    Remote(Request), // For the running instance
+    Version,
+    Help,
+}
+
+pub enum Share {
+    Host(u16),
+    Join(String), // host:port
+    Serve(u16),   // Hosting for viewers
+    View(String),
+}
+
+pub enum Freeze {
+    Capture,
+    File(PathBuf),
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub config: Option<PathBuf>,
+    pub background: Option<BackgroundSpec>,
+    pub fullscreen: bool,
+    pub monitor: Option<usize>,
+    pub output_dir: Option<PathBuf>,
+    pub load: Option<PathBuf>,
+    pub freeze: Option<Freeze>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub replay_speed: Option<f32>,
+    pub frames: Option<PathBuf>,
+    pub frames_raw: bool,
+    pub frames_fps: Option<u32>,
+    pub share: Option<Share>,
+    pub bind: Option<IpAddr>,
+    pub name: Option<String>,
+    pub http: Option<u16>,
+}
+
+impl Options {
+    // What was asked for on the command line, as it is spelled in `USAGE`
+    pub fn given(&self) -> Vec<&'static str> {
+        [
+            (self.config.is_some(), "--config"),
+            (self.background.is_some(), "--background"),
+            (self.fullscreen, "--fullscreen"),
+            (self.monitor.is_some(), "--monitor"),
+            (self.output_dir.is_some(), "--output-dir"),
+            (self.load.is_some(), "FILE"),
+            (self.freeze.is_some(), "--freeze"),
+            (self.record.is_some(), "--record"),
+            (self.replay.is_some(), "--replay"),
+            (self.frames.is_some(), "--frames"),
+            (self.http.is_some(), "--http"),
+        ]
+        .into_iter()
+        .filter_map(|(is_given, name)| is_given.then_some(name))
+        .collect()
+    }
+}
+
+// Arguments without the program name. The error is meant to be shown above
+// `USAGE`
+pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let remote = match args.peek().and_then(|arg| arg.to_str()) {
+        Some("render") => {
+            args.next();
+            return parse_render(args);
+        }
+        Some("timelapse") => {
+            args.next();
+            return parse_timelapse(args);
+        }
+        Some("toggle") => Some(Request::ToggleVisibility),
+        Some("clear") => Some(Request::Clear),
+        _ => None,
+    };
+    if let Some(request) = remote {
+        let name = args.next().unwrap_or_default();
+        if let Some(arg) = args.next() {
+            return Err(format!(
+                "unexpected {} after {}",
+                arg.to_string_lossy(),
+                name.to_string_lossy()
+            ));
+        }
+        return Ok(Command::Remote(request));
+    }
+
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.to_str().filter(|arg| arg.starts_with('-')) else {
+            set_once(&mut options.load, PathBuf::from(arg), "FILE")?;
+            continue;
+        };
+
+        match flag {
+            "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+            "--fullscreen" => options.fullscreen = true,
+            "--freeze" => options.freeze = Some(Freeze::Capture),
+            "--freeze-from" => {
+                options.freeze = Some(Freeze::File(value(&mut args, flag)?.into()));
+            }
+            "--config" => set_once(&mut options.config, value(&mut args, flag)?.into(), flag)?,
+            "--output-dir" => set_once(
+                &mut options.output_dir,
+                value(&mut args, flag)?.into(),
+                flag,
+            )?,
+            "--load" => set_once(&mut options.load, value(&mut args, flag)?.into(), flag)?,
+            "--record" => set_once(&mut options.record, value(&mut args, flag)?.into(), flag)?,
+            "--replay" => set_once(&mut options.replay, value(&mut args, flag)?.into(), flag)?,
+            "--frames" => set_once(&mut options.frames, value(&mut args, flag)?.into(), flag)?,
+            "--frames-raw" => options.frames_raw = true,
+            "--frames-fps" => {
+                let fps = number(&mut args, flag, MIN_FRAMES_FPS, MAX_FRAMES_FPS)?;
+                set_once(&mut options.frames_fps, fps, flag)?;
+            }
+            "--host" | "--serve" => {
+                let port = number(&mut args, flag, 1, u16::MAX)?;
+                let share = if flag == "--host" {
+                    Share::Host(port)
+                } else {
+                    Share::Serve(port)
+                };
+                set_once(&mut options.share, share, SHARE_FLAGS)?;
+            }
+            "--join" | "--view" => {
+                let address = string(&mut args, flag)?;
+                if !address.contains(':') {
+                    return Err(format!("{flag} takes HOST:PORT"));
+                }
+                let share = if flag == "--join" {
+                    Share::Join(address)
+                } else {
+                    Share::View(address)
+                };
+                set_once(&mut options.share, share, SHARE_FLAGS)?;
+            }
+            "--http" => {
+                let port = number(&mut args, flag, 1, u16::MAX)?;
+                set_once(&mut options.http, port, flag)?;
+            }
+            "--bind" => {
+                let address = string(&mut args, flag)?
+                    .parse()
+                    .map_err(|_| format!("{flag} takes an IP address"))?;
+                set_once(&mut options.bind, address, flag)?;
+            }
+            "--name" => set_once(&mut options.name, string(&mut args, flag)?, flag)?,
+            "--replay-speed" => {
+                let speed = number(&mut args, flag, MIN_REPLAY_SPEED, MAX_REPLAY_SPEED)?;
+                set_once(&mut options.replay_speed, speed, flag)?;
+            }
+            "--background" => {
+                let value = value(&mut args, flag)?;
+                let value = value
+                    .to_str()
+                    .ok_or_else(|| format!("invalid value for {flag}"))?;
+                set_once(
+                    &mut options.background,
+                    config::parse_background(value)?,
+                    flag,
+                )?;
+            }
+            "--monitor" => {
+                let value = value(&mut args, flag)?;
+                let monitor = value
+                    .to_str()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| format!("{flag} takes a monitor number, counted from 0"))?;
+                set_once(&mut options.monitor, monitor, flag)?;
+            }
+            _ => return Err(format!("unknown option {flag}")),
+        }
+    }
+
+    if options.replay_speed.is_some() && options.replay.is_none() {
+        return Err("--replay-speed needs --replay".to_string());
+    }
+    let is_stdout = options
+        .frames
+        .as_ref()
+        .is_some_and(|target| target.as_os_str() == "-");
+    if options.frames_raw && !is_stdout {
+        return Err("--frames-raw needs --frames -".to_string());
+    }
+    if options.frames_fps.is_some() && options.frames.is_none() {
+        return Err("--frames-fps needs --frames".to_string());
+    }
+    if options.name.is_some() && options.share.is_none() {
+        return Err(format!("--name needs {SHARE_FLAGS}"));
+    }
+    let is_hosting = matches!(options.share, Some(Share::Host(_) | Share::Serve(_)));
+    if options.bind.is_some() && !is_hosting {
+        return Err("--bind needs --host or --serve".to_string());
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+fn parse_render(mut args: impl Iterator<Item = OsString>) -> Result<Command, String> {
+    let mut input = None;
+    let mut output: Option<PathBuf> = None;
+    let mut format = None;
+    let mut scale = None;
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-o" | "--output") => set_once(&mut output, value(&mut args, "-o")?.into(), "-o")?,
+            Some(flag @ "--format") => {
+                let value = match value(&mut args, flag)?.to_str() {
+                    Some("png") => Format::Png,
+                    Some("svg") => Format::Svg,
+                    _ => return Err(format!("{flag} takes png or svg")),
+                };
+                set_once(&mut format, value, flag)?;
+            }
+            Some(flag @ "--scale") => {
+                set_once(
+                    &mut scale,
+                    number(&mut args, flag, MIN_SCALE, MAX_SCALE)?,
+                    flag,
+                )?;
+            }
+            Some(flag) if flag.starts_with('-') => {
+                return Err(format!("unknown option {flag} for render"));
+            }
+            _ => set_once(&mut input, PathBuf::from(arg), "FILE.dos")?,
+        }
+    }
+
+    let input = input.ok_or("render needs a session file")?;
+    let format = format.unwrap_or_else(|| {
+        let is_svg = output
+            .as_ref()
+            .and_then(|output| output.extension())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+        if is_svg { Format::Svg } else { Format::Png }
+    });
+    Ok(Command::Render {
+        input,
+        output,
+        format,
+        scale: scale.unwrap_or(1.0),
+    })
+}
+
+fn parse_timelapse(mut args: impl Iterator<Item = OsString>) -> Result<Command, String> {
+    let mut input = None;
+    let mut output: Option<PathBuf> = None;
+    let mut format = None;
+    let mut fps = None;
+    let mut duration = None;
+    let mut scale = None;
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-o" | "--output") => set_once(&mut output, value(&mut args, "-o")?.into(), "-o")?,
+            Some(flag @ "--format") => {
+                let value = match value(&mut args, flag)?.to_str() {
+                    Some("gif") => AnimationFormat::Gif,
+                    Some("apng") => AnimationFormat::Apng,
+                    _ => return Err(format!("{flag} takes gif or apng")),
+                };
+                set_once(&mut format, value, flag)?;
+            }
+            Some(flag @ "--fps") => {
+                set_once(&mut fps, number(&mut args, flag, MIN_FPS, MAX_FPS)?, flag)?;
+            }
+            Some(flag @ "--duration") => {
+                let value = number(&mut args, flag, MIN_DURATION, MAX_DURATION)?;
+                set_once(&mut duration, value, flag)?;
+            }
+            Some(flag @ "--scale") => {
+                set_once(
+                    &mut scale,
+                    number(&mut args, flag, MIN_SCALE, MAX_SCALE)?,
+                    flag,
+                )?;
+            }
+            Some(flag) if flag.starts_with('-') => {
+                return Err(format!("unknown option {flag} for timelapse"));
+            }
+            _ => set_once(&mut input, PathBuf::from(arg), "FILE.dos")?,
+        }
+    }
+
+    let input = input.ok_or("timelapse needs a session file")?;
+    // An animated PNG is still a PNG
+    let format = format.unwrap_or_else(|| {
+        let is_png = output
+            .as_ref()
+            .and_then(|output| output.extension())
+            .is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("png") || extension.eq_ignore_ascii_case("apng")
+            });
+        if is_png {
+            AnimationFormat::Apng
+        } else {
+            AnimationFormat::Gif
+        }
+    });
+    Ok(Command::Timelapse {
+        input,
+        output,
+        format,
+        fps: fps.unwrap_or(10),
+        duration: duration.unwrap_or(5.0),
+        scale: scale.unwrap_or(1.0),
+    })
+}
+
+// A number from `min` to `max`
+fn number<T>(
+    args: &mut impl Iterator<Item = OsString>,
+    flag: &str,
+    min: T,
+    max: T,
+) -> Result<T, String>
+where
+    T: Copy + std::str::FromStr + PartialOrd + std::fmt::Display,
+{
+    value(args, flag)?
+        .to_str()
+        .and_then(|value| value.parse::<T>().ok())
+        .filter(|number| (min..=max).contains(number))
+        .ok_or_else(|| format!("{flag} takes a number from {min} to {max}"))
+}
+
+fn value(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<OsString, String> {
+    args.next().ok_or_else(|| format!("{flag} needs a value"))
+}
+
+fn string(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<String, String> {
+    value(args, flag)?
+        .into_string()
+        .map_err(|_| format!("invalid value for {flag}"))
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T, name: &str) -> Result<(), String> {
+    if slot.is_some() {
+        return Err(format!("{name} given more than once"));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(parse_args(&["-h"]).err().unwrap(), "unknown option -h");
+        assert_eq!(
+            parse_args(&["--nope"]).err().unwrap(),
+            "unknown option --nope"
+        );
+    }
+
+    #[test]
+    fn takes_a_file() {
+        let Ok(Command::Run(options)) = parse_args(&["notes.dos", "--fullscreen"]) else {
+            panic!("expected a run");
+        };
+        assert_eq!(options.load, Some(PathBuf::from("notes.dos")));
+        assert!(options.fullscreen);
+        assert_eq!(options.given(), ["--fullscreen", "FILE"]);
+    }
+
+    #[test]
+    fn rejects_repeated_options() {
+        assert_eq!(
+            parse_args(&["--background", "grid", "--background", "dots"])
+                .err()
+                .unwrap(),
+            "--background given more than once"
+        );
+        assert_eq!(
+            parse_args(&["--monitor", "0", "--monitor", "1"])
+                .err()
+                .unwrap(),
+            "--monitor given more than once"
+        );
+    }
+
+    #[test]
+    fn binds_only_when_hosting() {
+        let Ok(Command::Run(options)) = parse_args(&["--host", "7878", "--bind", "0.0.0.0"]) else {
+            panic!("expected a run");
+        };
+        assert_eq!(options.bind, Some(IpAddr::from([0, 0, 0, 0])));
+        assert_eq!(
+            parse_args(&["--join", "host:7878", "--bind", "0.0.0.0"])
+                .err()
+                .unwrap(),
+            "--bind needs --host or --serve"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+// Images on the system clipboard. Pasting goes through `arboard`; copying
+// offers the PNG from `export::encode_png` under `image/png` itself, since
+// `arboard` only takes pixels and encodes them its own way. On Wayland that is
+// the data-control protocol, elsewhere (and under XWayland when the compositor
+// lacks it) a small X11 selection owner
+use std::{error::Error, thread};
+
+use arboard::Clipboard;
+use wl_clipboard_rs::copy::{MimeType, Options, Source};
+use x11rb::{
+    CURRENT_TIME, NONE,
+    connection::{Connection, RequestConnection},
+    protocol::{
+        Event,
+        xproto::{
+            AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask,
+            PropMode, Property, SELECTION_NOTIFY_EVENT, SelectionNotifyEvent,
+            SelectionRequestEvent, Window, WindowClass,
+        },
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+use crate::{export, image::Image};
+
+const PNG_MIME: &str = "image/png";
+
+// Read an image from the system clipboard. `arboard` asks the X11/Wayland
+// selection owner for the `image/png` target and decodes it for us
+pub fn read_image(clipboard: &mut Clipboard) -> Result<Image, arboard::Error> {
+    let data = clipboard.get_image()?;
+
+    if data.width == 0 || data.height == 0 {
+        return Err(arboard::Error::ContentNotAvailable);
+    }
+
+    Ok(Image::from_rgba(
+        data.width as u32,
+        data.height as u32,
+        &data.bytes,
+    ))
+}
+
+// Offer RGBA pixels as `image/png`, encoded the same way saved files are, so
+// the alpha of a transparent background survives. A thread keeps serving the
+// PNG until something else is copied
+pub fn write_image(width: u32, height: u32, rgba: &[u8]) -> Result<(), Box<dyn Error>> {
+    let png = export::encode_png(width, height, rgba)?;
+
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let source = Source::Bytes(png.clone().into_boxed_slice());
+        match Options::new().copy(source, MimeType::Specific(PNG_MIME.to_string())) {
+            Ok(()) => return Ok(()),
+            Err(err) if std::env::var_os("DISPLAY").is_none() => return Err(err.into()),
+            Err(_) => {} // Try XWayland
+        }
+    }
+    offer_x11(png)
+}
+
+struct Atoms {
+    clipboard: u32,
+    targets: u32,
+    png: u32,
+    incr: u32,
+}
+
+// A PNG too large for one request, handed out a chunk every time the requestor
+// deletes the property it read the last one from
+struct Transfer {
+    requestor: Window,
+    property: u32,
+    sent: usize,
+}
+
+// Own the CLIPBOARD selection from a connection and window of our own
+fn offer_x11(png: Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let (connection, screen) = x11rb::connect(None)?;
+    let root = connection.setup().roots[screen].root;
+    let window = connection.generate_id()?;
+    connection.create_window(
+        x11rb::COPY_DEPTH_FROM_PARENT,
+        window,
+        root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        x11rb::COPY_FROM_PARENT,
+        &CreateWindowAux::new(),
+    )?;
+
+    let intern = |name: &[u8]| -> Result<u32, Box<dyn Error>> {
+        Ok(connection.intern_atom(false, name)?.reply()?.atom)
+    };
+    let atoms = Atoms {
+        clipboard: intern(b"CLIPBOARD")?,
+        targets: intern(b"TARGETS")?,
+        png: intern(PNG_MIME.as_bytes())?,
+        incr: intern(b"INCR")?,
+    };
+
+    connection.set_selection_owner(window, atoms.clipboard, CURRENT_TIME)?;
+    if connection
+        .get_selection_owner(atoms.clipboard)?
+        .reply()?
+        .owner
+        != window
+    {
+        return Err("another client kept the clipboard".into());
+    }
+
+    thread::spawn(move || {
+        if let Err(err) = serve_x11(&connection, &atoms, &png) {
+            eprintln!("Stopped offering the copied image: {err}");
+        }
+    });
+    Ok(())
+}
+
+// Answer requests for the selection until another client takes it over
+fn serve_x11(connection: &RustConnection, atoms: &Atoms, png: &[u8]) -> Result<(), Box<dyn Error>> {
+    // Property data has to fit in one request, leaving room for its header
+    let chunk = connection.maximum_request_bytes() / 2;
+    let mut transfers: Vec<Transfer> = Vec::new();
+
+    loop {
+        match connection.wait_for_event()? {
+            Event::SelectionClear(_) => return Ok(()),
+            Event::SelectionRequest(request) => {
+                let property = answer(connection, atoms, png, chunk, &request)?;
+                if let Some(property) = property
+                    && request.target == atoms.png
+                    && png.len() > chunk
+                {
+                    transfers.push(Transfer {
+                        requestor: request.requestor,
+                        property,
+                        sent: 0,
+                    });
+                }
+            }
+            Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                let Some(index) = transfers.iter().position(|transfer| {
+                    transfer.requestor == event.window && transfer.property == event.atom
+                }) else {
+                    continue;
+                };
+                // The last chunk is an empty one
+                let transfer = &mut transfers[index];
+                let end = png.len().min(transfer.sent + chunk);
+                connection.change_property8(
+                    PropMode::REPLACE,
+                    transfer.requestor,
+                    transfer.property,
+                    atoms.png,
+                    &png[transfer.sent..end],
+                )?;
+                if transfer.sent == end {
+                    transfers.swap_remove(index);
+                } else {
+                    transfer.sent = end;
+                }
+                connection.flush()?;
+            }
+            _ => {}
+        }
+    }
+}
+
+// Store what `request` asks for on the requestor and tell it where, returning
+// the property or `None` for targets we don't have
+fn answer(
+    connection: &RustConnection,
+    atoms: &Atoms,
+    png: &[u8],
+    chunk: usize,
+    request: &SelectionRequestEvent,
+) -> Result<Option<u32>, Box<dyn Error>> {
+    // Obsolete clients leave the property out and mean the target
+    let property = match request.property {
+        NONE => request.target,
+        property => property,
+    };
+
+    let stored = if request.target == atoms.targets {
+        connection.change_property32(
+            PropMode::REPLACE,
+            request.requestor,
+            property,
+            AtomEnum::ATOM,
+            &[atoms.targets, atoms.png],
+        )?;
+        true
+    } else if request.target == atoms.png && png.len() <= chunk {
+        connection.change_property8(
+            PropMode::REPLACE,
+            request.requestor,
+            property,
+            atoms.png,
+            png,
+        )?;
+        true
+    } else if request.target == atoms.png {
+        // Too large for one go: announce the size, then hand out chunks as
+        // the requestor deletes the property
+        connection.change_window_attributes(
+            request.requestor,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+        connection.change_property32(
+            PropMode::REPLACE,
+            request.requestor,
+            property,
+            atoms.incr,
+            &[png.len() as u32],
+        )?;
+        true
+    } else {
+        false
+    };
+
+    connection.send_event(
+        false,
+        request.requestor,
+        EventMask::NO_EVENT,
+        SelectionNotifyEvent {
+            response_type: SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if stored { property } else { NONE },
+        },
+    )?;
+    connection.flush()?;
+    Ok(stored.then_some(property))
+}
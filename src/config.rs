@@ -0,0 +1,298 @@
+// Settings read from a plain `key = value` file, one per line, with lines
+// starting with `#` being comments. Everything is optional and command line
+// options win over the file
+//
+//   palette    = #ef4444, #22c55e, #3b82f6, #fafafa  (colors behind 1, 2, 3, 0)
+//   color      = #fafafa                             (pen color at startup)
+//   brush      = 1                                   (brush radius, 1 to 20)
+//   eraser     = 3                                   (eraser radius, 1 to 50)
+//   background = grid                                (see `parse_background`)
+//   spacing    = 40                                  (grid, dot and rule spacing)
+//   output_dir = ~/Pictures/annotations              (where exports are saved)
+//   fullscreen = true
+//   monitor    = 0                                   (counted from 0)
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::background::{self, Pattern};
+
+#[derive(Default)]
+pub struct Config {
+    pub palette: Option<Vec<u32>>,
+    pub pointer_color: Option<u32>,
+    pub draw_brush_radius: Option<i32>,
+    pub erase_brush_radius: Option<i32>,
+    pub background: Option<BackgroundSpec>,
+    pub spacing: Option<f32>,
+    pub output_dir: Option<PathBuf>,
+    pub fullscreen: bool,
+    pub monitor: Option<usize>,
+}
+
+pub enum BackgroundSpec {
+    Pattern(Pattern),
+    Color(u32),
+    Image(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Invalid { line: usize, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{err}"),
+            ConfigError::Invalid { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Self::parse(&fs::read_to_string(path).map_err(ConfigError::Io)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let invalid = |reason: String| ConfigError::Invalid {
+                line: index + 1,
+                reason,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(invalid(format!("expected `key = value`, got `{line}`")));
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "palette" => {
+                    let colors = value
+                        .split(',')
+                        .map(|color| parse_color(color.trim()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(invalid)?;
+                    if colors.is_empty() || colors.len() > 4 {
+                        return Err(invalid("palette takes one to four colors".to_string()));
+                    }
+                    config.palette = Some(colors);
+                }
+                "color" => config.pointer_color = Some(parse_color(value).map_err(invalid)?),
+                "brush" => {
+                    config.draw_brush_radius = Some(parse_number(value, 1, 20).map_err(invalid)?)
+                }
+                "eraser" => {
+                    config.erase_brush_radius = Some(parse_number(value, 1, 50).map_err(invalid)?)
+                }
+                "background" => config.background = Some(parse_background(value).map_err(invalid)?),
+                "spacing" => {
+                    let spacing = value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|spacing| {
+                            (background::MIN_SPACING..=background::MAX_SPACING).contains(spacing)
+                        })
+                        .ok_or_else(|| {
+                            invalid(format!(
+                                "spacing must be between {} and {}",
+                                background::MIN_SPACING,
+                                background::MAX_SPACING
+                            ))
+                        })?;
+                    config.spacing = Some(spacing);
+                }
+                "output_dir" => config.output_dir = Some(expand_home(value)),
+                "fullscreen" => {
+                    config.fullscreen = match value {
+                        "true" | "yes" | "1" => true,
+                        "false" | "no" | "0" => false,
+                        _ => return Err(invalid(format!("expected true or false, got `{value}`"))),
+                    }
+                }
+                "monitor" => {
+                    config.monitor = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid(format!("invalid monitor `{value}`")))?,
+                    )
+                }
+                _ => return Err(invalid(format!("unknown setting `{key}`"))),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_number(value: &str, min: i32, max: i32) -> Result<i32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|number| (min..=max).contains(number))
+        .ok_or_else(|| format!("expected a number from {min} to {max}, got `{value}`"))
+}
+
+// `#rrggbb`
+pub fn parse_color(value: &str) -> Result<u32, String> {
+    value
+        .strip_prefix('#')
+        // `from_str_radix` would also take a sign
+        .filter(|hex| hex.len() == 6 && hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| format!("expected a color like #fafafa, got `{value}`"))
+}
+
+// A pattern name (solid, grid, dots, lines, transparent), a `#rrggbb` color
+// or the path of a PNG to draw on
+pub fn parse_background(value: &str) -> Result<BackgroundSpec, String> {
+    Ok(match value {
+        "solid" => BackgroundSpec::Pattern(Pattern::Solid),
+        "grid" => BackgroundSpec::Pattern(Pattern::Grid),
+        "dots" => BackgroundSpec::Pattern(Pattern::Dots),
+        "lines" => BackgroundSpec::Pattern(Pattern::Lines),
+        "transparent" => BackgroundSpec::Pattern(Pattern::Transparent),
+        color if color.starts_with('#') => BackgroundSpec::Color(parse_color(color)?),
+        path if path.to_ascii_lowercase().ends_with(".png") => {
+            BackgroundSpec::Image(expand_home(path))
+        }
+        _ => {
+            return Err(format!(
+                "expected solid, grid, dots, lines, transparent, a #rrggbb color or a PNG file, got `{value}`"
+            ));
+        }
+    })
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+// $XDG_CONFIG_HOME/drawonscreen/config (~/.config by default)
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("drawonscreen").join("config"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, ffi::OsString};
+
+    use super::*;
+    use crate::{
+        DrawOnScreen,
+        cli::{self, Command},
+    };
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ef4444"), Ok(0xef4444));
+        assert_eq!(parse_color("#FAFAFA"), Ok(0xfafafa));
+        for invalid in [
+            "ef4444", "#ef444", "#ef44444", "#+ef444", "#-00000", "#gggggg", "# 12345",
+        ] {
+            assert!(parse_color(invalid).is_err(), "{invalid} was taken");
+        }
+    }
+
+    #[test]
+    fn parses_settings() {
+        let config = Config::parse(
+            "# comment\n\
+             palette = #ef4444, #22c55e\n\
+             color = #fafafa\n\
+             brush = 4\n\
+             background = dots\n\
+             spacing = 30\n\
+             fullscreen = yes\n\
+             monitor = 1\n",
+        )
+        .unwrap();
+        assert_eq!(config.palette, Some(vec![0xef4444, 0x22c55e]));
+        assert_eq!(config.pointer_color, Some(0xfafafa));
+        assert_eq!(config.draw_brush_radius, Some(4));
+        assert!(matches!(
+            config.background,
+            Some(BackgroundSpec::Pattern(Pattern::Dots))
+        ));
+        assert_eq!(config.spacing, Some(30.0));
+        assert!(config.fullscreen);
+        assert_eq!(config.monitor, Some(1));
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for (text, line) in [
+            ("brush = 21", 1),
+            ("# fine\neraser = 0", 2),
+            ("spacing = 1", 1),
+            ("palette = #000000, #111111, #222222, #333333, #444444", 1),
+            ("fullscreen = maybe", 1),
+            ("colour = #ffffff", 1),
+            ("color", 1),
+        ] {
+            assert!(
+                matches!(Config::parse(text), Err(ConfigError::Invalid { line: at, .. }) if at == line),
+                "{text} was taken"
+            );
+        }
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let directory = env::temp_dir().join(format!("drawonscreen-config-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config");
+        fs::write(
+            &path,
+            format!(
+                "color = #22c55e\nbackground = dots\nmonitor = 1\noutput_dir = {}\n",
+                directory.join("from-config").display()
+            ),
+        )
+        .unwrap();
+
+        let output_dir = directory.join("from-command-line");
+        let args = [
+            OsString::from("--config"),
+            path.into(),
+            "--background".into(),
+            "grid".into(),
+            "--monitor".into(),
+            "2".into(),
+            "--output-dir".into(),
+            output_dir.clone().into(),
+        ];
+        let Ok(Command::Run(options)) = cli::parse(args) else {
+            panic!("expected a run");
+        };
+        let mut app = DrawOnScreen::default();
+        app.apply_options(*options).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(app.background.pattern == Pattern::Grid);
+        assert_eq!(app.monitor, Some(2));
+        assert_eq!(app.output_dir, Some(output_dir));
+        // What the command line leaves alone still comes from the file
+        assert_eq!(app.pointer_color, 0x22c55e);
+    }
+}
@@ -0,0 +1,313 @@
+// Scripting a running instance through a Unix socket in $XDG_RUNTIME_DIR. Each
+// line a client writes is one JSON command, answered with one JSON line:
+//
+//   {"command": "set_color", "color": "#ef4444"}   ->  {"ok":true}
+//   {"command": "set_tool", "tool": "eraser"}      ->  {"ok":true}
+//   {"command": "save"}                            ->  {"ok":true,"path":"..."}
+//   {"command": "nope"}                            ->  {"ok":false,"error":"..."}
+//
+// Commands are handed to the event loop as user events and run there, on the
+// same code paths as the keyboard and mouse
+//
+// Only one instance runs at a time: the first holds an exclusive lock on
+// $XDG_RUNTIME_DIR/drawonscreen.lock for as long as it lives, and later ones
+// pass their command to it over the socket instead of opening a window
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use winit::event_loop::EventLoopProxy;
+
+#[cfg(feature = "http")]
+use crate::http::CanvasImage;
+use crate::shared::PeerEvent;
+
+const SOCKET_NAME: &str = "drawonscreen.sock";
+const LOCK_NAME: &str = "drawonscreen.lock";
+
+// How long `send` waits for an instance that is still starting up
+const CONNECT_ATTEMPTS: u32 = 20;
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(100);
+// How long `send` waits for the answer, a save of a large canvas included
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    SetColor { color: String },
+    SetTool { tool: Tool },
+    Clear,
+    Undo,
+    Save,
+    Show, // Unhide and raise the window
+    Hide,
+    ToggleVisibility,
+    Quit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tool {
+    Pen,
+    Eraser,
+}
+
+impl Tool {
+    // As in the JSON commands
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Pen => "pen",
+            Tool::Eraser => "eraser",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>, // Where `save` wrote to
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+            path: None,
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.into()),
+            path: None,
+        }
+    }
+
+    pub fn saved(path: String) -> Self {
+        Self {
+            path: Some(path),
+            ..Self::ok()
+        }
+    }
+}
+
+// A command on its way to the event loop, with where to send the answer
+pub struct ControlEvent {
+    pub request: Request,
+    pub reply: mpsc::Sender<Reply>,
+}
+
+// Everything other threads hand to the event loop
+pub enum UserEvent {
+    Control(ControlEvent),
+    Peer(PeerEvent), // From the other instances on a shared canvas
+    FrameWanted,     // The `--frames` writer is waiting for a present
+    #[cfg(feature = "http")]
+    Canvas(mpsc::Sender<CanvasImage>), // For `/canvas.png`
+}
+
+// The bound socket. The file is removed again when this is dropped
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// `name` in $XDG_RUNTIME_DIR, the per user directory for sockets and locks
+pub fn runtime_path(name: &str) -> io::Result<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .map(|runtime_dir| runtime_dir.join(name))
+        .ok_or_else(|| io::Error::other("XDG_RUNTIME_DIR is not set"))
+}
+
+// Bind the socket and answer clients on background threads
+pub fn listen(proxy: EventLoopProxy<UserEvent>) -> io::Result<ControlSocket> {
+    let path = runtime_path(SOCKET_NAME)?;
+
+    // A socket file nobody answers on was left behind by a crash
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another instance", path.display()),
+            ));
+        }
+        fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let proxy = proxy.clone();
+            thread::spawn(move || serve(stream, proxy));
+        }
+    });
+
+    Ok(ControlSocket { path })
+}
+
+fn serve(stream: UnixStream, proxy: EventLoopProxy<UserEvent>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => dispatch(&proxy, request),
+            Err(err) => Reply::error(format!("invalid command: {err}")),
+        };
+        let Ok(mut reply) = serde_json::to_string(&reply) else {
+            return;
+        };
+        reply.push('\n');
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+// Send a request to the running instance and wait for its answer. The
+// instance may have only just started, so the socket gets a moment to show up
+pub fn send(request: &Request) -> io::Result<Reply> {
+    let path = runtime_path(SOCKET_NAME)?;
+    let mut attempts = 0;
+    let stream = loop {
+        match UnixStream::connect(&path) {
+            Ok(stream) => break stream,
+            Err(_) if attempts < CONNECT_ATTEMPTS => {
+                attempts += 1;
+                thread::sleep(CONNECT_RETRY_DELAY);
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    let mut line = serde_json::to_string(request).map_err(io::Error::other)?;
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+
+    // An instance whose event loop is stuck shouldn't hang the caller too
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut reply = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut reply)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the running instance didn't answer",
+            ),
+            _ => err,
+        })?;
+    serde_json::from_str(&reply).map_err(io::Error::other)
+}
+
+// Run a request on the event loop and wait for its answer
+pub fn dispatch(proxy: &EventLoopProxy<UserEvent>, request: Request) -> Reply {
+    let (sender, receiver) = mpsc::channel();
+    let event = ControlEvent {
+        request,
+        reply: sender,
+    };
+    if proxy.send_event(UserEvent::Control(event)).is_err() {
+        return Reply::error("shutting down");
+    }
+    receiver
+        .recv()
+        .unwrap_or_else(|_| Reply::error("shutting down"))
+}
+
+// Held by the one instance that owns the socket, released when it exits
+pub struct InstanceLock {
+    _file: File,
+}
+
+// The lock, or `None` when another instance holds it
+pub fn lock_instance() -> io::Result<Option<InstanceLock>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(runtime_path(LOCK_NAME)?)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(InstanceLock { _file: file })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(err)) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn to_json(value: &impl Serialize) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let request = Request::SetColor {
+            color: "#ef4444".to_string(),
+        };
+        let json = to_json(&request);
+        assert_eq!(json, json!({"command": "set_color", "color": "#ef4444"}));
+        let Ok(Request::SetColor { color }) = serde_json::from_value(json) else {
+            panic!("expected set_color");
+        };
+        assert_eq!(color, "#ef4444");
+
+        let Ok(Request::SetTool { tool }) =
+            serde_json::from_str(r#"{"command": "set_tool", "tool": "eraser"}"#)
+        else {
+            panic!("expected set_tool");
+        };
+        assert!(tool == Tool::Eraser);
+        assert_eq!(
+            to_json(&Request::ToggleVisibility),
+            json!({"command": "toggle_visibility"})
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"command": "nope"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r##"{"color": "#ef4444"}"##).is_err());
+    }
+
+    #[test]
+    fn replies_round_trip() {
+        assert_eq!(to_json(&Reply::ok()), json!({"ok": true}));
+        assert_eq!(
+            to_json(&Reply::error("no window")),
+            json!({"ok": false, "error": "no window"})
+        );
+
+        let json = to_json(&Reply::saved("/tmp/drawing.png".to_string()));
+        assert_eq!(json, json!({"ok": true, "path": "/tmp/drawing.png"}));
+        let reply: Reply = serde_json::from_value(json).unwrap();
+        assert!(reply.ok && reply.error.is_none());
+        assert_eq!(reply.path.as_deref(), Some("/tmp/drawing.png"));
+    }
+}
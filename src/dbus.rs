@@ -0,0 +1,210 @@
+// The `org.drawonscreen.Control` service on the session bus, for desktop
+// widgets. Methods are the same requests the control socket takes, signals
+// announce changes made from anywhere (keyboard, socket or D-Bus)
+use std::{path::Path, sync::Arc};
+
+use ::blocking::unblock;
+use winit::event_loop::EventLoopProxy;
+use zbus::{blocking, fdo, interface, object_server::SignalEmitter};
+
+use crate::control::{self, Reply, Request, Tool, UserEvent};
+
+const NAME: &str = "org.drawonscreen.Control";
+const PATH: &str = "/org/drawonscreen/Control";
+
+// Hands a request to whoever runs it and waits for the answer
+type Dispatch = Arc<dyn Fn(Request) -> Reply + Send + Sync>;
+
+struct Control {
+    dispatch: Dispatch,
+}
+
+impl Control {
+    // Run a request on the event loop, giving the saved path if there is one.
+    // Waiting on the event loop happens on the blocking thread pool, so that the
+    // connection's executor keeps serving other calls meanwhile
+    async fn run(&self, request: Request) -> fdo::Result<Option<String>> {
+        let dispatch = Arc::clone(&self.dispatch);
+        let reply = unblock(move || dispatch(request)).await;
+        if reply.ok {
+            Ok(reply.path)
+        } else {
+            Err(fdo::Error::Failed(reply.error.unwrap_or_default()))
+        }
+    }
+}
+
+#[interface(name = "org.drawonscreen.Control")]
+impl Control {
+    // `#rrggbb`
+    async fn set_color(&self, color: &str) -> fdo::Result<()> {
+        self.run(Request::SetColor {
+            color: color.to_string(),
+        })
+        .await
+        .map(drop)
+    }
+
+    async fn undo(&self) -> fdo::Result<()> {
+        self.run(Request::Undo).await.map(drop)
+    }
+
+    async fn clear(&self) -> fdo::Result<()> {
+        self.run(Request::Clear).await.map(drop)
+    }
+
+    // Saves a PNG like Ctrl+S and returns its path
+    async fn save(&self) -> fdo::Result<String> {
+        self.run(Request::Save).await.map(Option::unwrap_or_default)
+    }
+
+    async fn show(&self) -> fdo::Result<()> {
+        self.run(Request::Show).await.map(drop)
+    }
+
+    async fn hide(&self) -> fdo::Result<()> {
+        self.run(Request::Hide).await.map(drop)
+    }
+
+    // The left button tool ("pen" or "eraser") and the pen color as `#rrggbb`
+    #[zbus(signal)]
+    async fn tool_changed(emitter: &SignalEmitter<'_>, tool: &str, color: &str)
+    -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn saved(emitter: &SignalEmitter<'_>, path: &str) -> zbus::Result<()>;
+}
+
+// The connection owning the bus name, kept for as long as the service should
+// stay up
+pub struct DbusService {
+    connection: blocking::Connection,
+}
+
+impl DbusService {
+    pub fn tool_changed(&self, tool: Tool, color: u32) {
+        let color = format!("#{:06x}", color & 0xFFFFFF);
+        self.emit("ToolChanged", &(tool.name(), color.as_str()));
+    }
+
+    pub fn saved(&self, path: &Path) {
+        self.emit("Saved", &(path.display().to_string(),));
+    }
+
+    fn emit<B>(&self, signal: &str, body: &B)
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        if let Err(err) = self
+            .connection
+            .emit_signal(None::<()>, PATH, NAME, signal, body)
+        {
+            eprintln!("Failed to send the {signal} D-Bus signal: {err}");
+        }
+    }
+}
+
+pub fn serve(proxy: EventLoopProxy<UserEvent>) -> zbus::Result<DbusService> {
+    let dispatch: Dispatch = Arc::new(move |request| control::dispatch(&proxy, request));
+    serve_on(blocking::connection::Builder::session()?, dispatch)
+}
+
+fn serve_on(
+    builder: blocking::connection::Builder<'_>,
+    dispatch: Dispatch,
+) -> zbus::Result<DbusService> {
+    let connection = builder
+        .name(NAME)?
+        .serve_at(PATH, Control { dispatch })?
+        .build()?;
+    Ok(DbusService { connection })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::DrawOnScreen;
+
+    // What a desktop widget sees of the service
+    #[zbus::proxy(
+        interface = "org.drawonscreen.Control",
+        default_service = "org.drawonscreen.Control",
+        default_path = "/org/drawonscreen/Control"
+    )]
+    trait Widget {
+        fn set_color(&self, color: &str) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn tool_changed(&self, tool: &str, color: &str) -> zbus::Result<()>;
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with --ignored"]
+    fn set_color_announces_the_tool() {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon should start");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim();
+
+        // Requests go to the application on this thread, as they would to the
+        // one on the event loop
+        let (requests, received) = mpsc::channel();
+        let dispatch: Dispatch = Arc::new(move |request| {
+            let (reply, replied) = mpsc::channel();
+            requests.send((request, reply)).unwrap();
+            replied.recv().unwrap()
+        });
+        let mut app = DrawOnScreen {
+            dbus: Some(
+                serve_on(
+                    blocking::connection::Builder::address(address).unwrap(),
+                    dispatch,
+                )
+                .unwrap(),
+            ),
+            ..DrawOnScreen::default()
+        };
+
+        let client = blocking::connection::Builder::address(address)
+            .unwrap()
+            .build()
+            .unwrap();
+        let widget = WidgetProxyBlocking::new(&client).unwrap();
+        let mut signals = widget.receive_tool_changed().unwrap();
+
+        let call = thread::spawn(move || widget.set_color("#ff8000"));
+        let (request, reply) = received.recv().unwrap();
+        reply.send(app.apply_request(request)).unwrap();
+        call.join().unwrap().unwrap();
+
+        // Waited for on the side, so a missing signal fails instead of hanging
+        let (announced, announcement) = mpsc::channel();
+        thread::spawn(move || {
+            let signal = signals.next().unwrap();
+            let args = signal.args().unwrap();
+            let _ = announced.send((args.tool.to_string(), args.color.to_string()));
+        });
+        let (tool, color) = announcement
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no ToolChanged signal");
+        assert_eq!((tool.as_str(), color.as_str()), ("pen", "#ff8000"));
+        assert_eq!(app.pointer_color, 0xff8000);
+
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+    }
+}
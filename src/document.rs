@@ -0,0 +1,53 @@
+// Geometry of what has been drawn. Coordinates are in world units, the raster
+// on screen is rendered from these through a `View`
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{image::Image, tiles::TileSnapshot, view::View};
+
+#[derive(Clone)]
+pub struct Stroke {
+    pub color: u32,
+    pub radius: f32,
+    pub is_eraser: bool,
+    pub points: Vec<(f32, f32)>,
+    pub id: Option<StrokeId>, // Only on a shared canvas
+}
+
+// Who drew a stroke on a shared canvas, and which of their strokes it is
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StrokeId {
+    pub peer: u32,
+    pub serial: u32,
+}
+
+#[derive(Clone)]
+pub enum Element {
+    Stroke(Stroke),
+    // A pasted image as it was committed: top-left corner and scaled size
+    Image {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        image: Arc<Image>,
+    },
+}
+
+// Everything an undo step has to bring back. The tiles are only a shortcut, the
+// elements alone are enough to render the page again
+pub struct Snapshot {
+    pub elements: Vec<Element>,
+    pub tiles: TileSnapshot,
+}
+
+// A whiteboard page that isn't on screen right now. The page being edited lives
+// directly in the `DrawOnScreen` fields of the same names
+#[derive(Default)]
+pub struct Page {
+    pub elements: Vec<Element>,
+    pub view: View,
+    pub undo_stack: Vec<Snapshot>,
+    pub redo_stack: Vec<Snapshot>,
+}
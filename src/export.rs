@@ -0,0 +1,57 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Convert 0xAARRGGBB pixels to tightly packed RGBA bytes. The alpha channel is
+// taken as is, so callers decide whether the background ends up transparent
+pub fn to_rgba(pixels: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(pixels.len() * 4);
+    for &pixel in pixels {
+        bytes.extend_from_slice(&[
+            (pixel >> 16) as u8,
+            (pixel >> 8) as u8,
+            pixel as u8,
+            (pixel >> 24) as u8,
+        ]);
+    }
+    bytes
+}
+
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, png::EncodingError> {
+    let mut bytes = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+
+    Ok(bytes)
+}
+
+pub fn save_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let bytes = encode_png(width, height, rgba).map_err(io::Error::other)?;
+    fs::write(path, bytes)
+}
+
+// <directory>/drawonscreen-<unix time>.png, the directory defaulting to
+// ~/Pictures and falling back to the working directory
+pub fn default_png_path(directory: Option<&Path>) -> PathBuf {
+    let directory = directory.map(Path::to_path_buf).unwrap_or_else(|| {
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join("Pictures"))
+            .filter(|pictures| pictures.is_dir())
+            .unwrap_or_else(|| PathBuf::from("."))
+    });
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    directory.join(format!("drawonscreen-{timestamp}.png"))
+}
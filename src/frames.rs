@@ -0,0 +1,347 @@
+// Dumping what the window shows as video frames at a fixed rate, for screen
+// recording pipelines:
+//
+//   drawonscreen_rust --frames - | ffmpeg -i - out.mp4
+//
+// A writer thread ticks every 1/fps seconds. When something was presented
+// since its last frame it asks the event loop for the window, and the next
+// present hands its pixels over; otherwise the frame it has is still current.
+// So the window is copied at most once per tick, however often it presents.
+// Drawing never waits for the disk or pipe: when the writer falls behind, the
+// frame it has gets written once for every tick it missed, so the video keeps
+// the length of the session
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    os::fd::{FromRawFd, RawFd},
+    path::PathBuf,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::{control::UserEvent, export};
+
+pub enum FrameTarget {
+    Png(PathBuf), // frame-000001.png, frame-000002.png, ... in this directory
+    Y4m,          // On stdout
+    Raw,          // RGBA on stdout
+}
+
+struct Latest {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+}
+
+struct Shared {
+    latest: Mutex<Option<Latest>>,
+    delivered: Condvar, // Signalled once a wanted frame is in `latest`
+    wanted: AtomicBool, // Only changed with `latest` locked
+    stale: AtomicBool,  // Something was presented since `latest` was taken
+    stop: AtomicBool,
+}
+
+// Stops the writer, after one last flush, when dropped
+pub struct FrameDump {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FrameDump {
+    pub fn start(
+        target: FrameTarget,
+        fps: u32,
+        proxy: EventLoopProxy<UserEvent>,
+    ) -> io::Result<Self> {
+        let output = match target {
+            FrameTarget::Png(directory) => {
+                fs::create_dir_all(&directory)?;
+                Output::Png(directory)
+            }
+            FrameTarget::Y4m => Output::Y4m(take_stdout()?),
+            FrameTarget::Raw => Output::Raw(take_stdout()?),
+        };
+        let shared = Arc::new(Shared {
+            latest: Mutex::new(None),
+            delivered: Condvar::new(),
+            wanted: AtomicBool::new(true),
+            stale: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        });
+        let writer = Writer {
+            output,
+            proxy,
+            fps,
+            size: None,
+            written: 0,
+        };
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || writer.run(&shared))
+        };
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    // Whether the writer is waiting for the next present
+    pub fn is_wanted(&self) -> bool {
+        self.shared.wanted.load(Ordering::Relaxed)
+    }
+
+    // The window as just presented, 0x00RRGGBB. Only copied when the writer
+    // asked for it
+    pub fn update(&self, width: u32, height: u32, pixels: &[u32]) {
+        if !self.is_wanted() {
+            self.shared.stale.store(true, Ordering::Relaxed);
+            return;
+        }
+        let Ok(mut latest) = self.shared.latest.lock() else {
+            return;
+        };
+        let latest = latest.get_or_insert_with(|| Latest {
+            width,
+            height,
+            pixels: Vec::new(),
+        });
+        latest.width = width;
+        latest.height = height;
+        latest.pixels.clear();
+        latest.pixels.extend_from_slice(pixels);
+        self.shared.wanted.store(false, Ordering::Relaxed);
+        self.shared.delivered.notify_one();
+    }
+}
+
+impl Drop for FrameDump {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.shared.delivered.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The video gets the real stdout, everything printed from now on goes to
+// stderr so it can't end up in the middle of a frame
+fn take_stdout() -> io::Result<BufWriter<File>> {
+    io::stdout().flush()?;
+    // SAFETY: plain descriptor calls, and `video` is a fresh duplicate that
+    // nothing but the returned file owns
+    unsafe {
+        let video: RawFd = libc::dup(libc::STDOUT_FILENO);
+        if video < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(BufWriter::new(File::from_raw_fd(video)))
+    }
+}
+
+enum Output {
+    Png(PathBuf),
+    Y4m(BufWriter<File>),
+    Raw(BufWriter<File>),
+}
+
+struct Writer {
+    output: Output,
+    proxy: EventLoopProxy<UserEvent>, // Woken up to present when a frame is wanted
+    fps: u32,
+    size: Option<(u32, u32)>, // Of the stream, fixed by its first frame
+    written: u64,
+}
+
+impl Writer {
+    fn run(mut self, shared: &Shared) {
+        let period = Duration::from_secs(1) / self.fps;
+        let mut pixels = Vec::new();
+        let mut next_tick = None;
+
+        while !shared.stop.load(Ordering::Relaxed) {
+            let tick = *next_tick.get_or_insert_with(Instant::now);
+            if let Some(wait) = tick.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+
+            let mut latest = shared.latest.lock().unwrap_or_else(|err| err.into_inner());
+            if shared.stale.swap(false, Ordering::Relaxed) {
+                shared.wanted.store(true, Ordering::Relaxed);
+            }
+            // Give the window up to a tick to present, after which the frame
+            // it has will do
+            if shared.wanted.load(Ordering::Relaxed) {
+                let _ = self.proxy.send_event(UserEvent::FrameWanted);
+                latest = shared
+                    .delivered
+                    .wait_timeout_while(latest, period, |_| {
+                        shared.wanted.load(Ordering::Relaxed)
+                            && !shared.stop.load(Ordering::Relaxed)
+                    })
+                    .unwrap_or_else(|err| err.into_inner())
+                    .0;
+            }
+            // Nothing to write before the window first shows up
+            let (width, height) = match &*latest {
+                Some(latest) => {
+                    pixels.clone_from(&latest.pixels);
+                    (latest.width, latest.height)
+                }
+                None => {
+                    drop(latest);
+                    next_tick = None;
+                    continue;
+                }
+            };
+            drop(latest);
+
+            let ticks = 1 + (Instant::now() - tick).as_nanos() / period.as_nanos();
+            if let Err(err) = self.write(&pixels, width, height, ticks as u64) {
+                eprintln!("Stopped writing frames: {err}");
+                return;
+            }
+            next_tick = Some(tick + period * ticks as u32);
+        }
+
+        if let Output::Y4m(out) | Output::Raw(out) = &mut self.output {
+            let _ = out.flush();
+        }
+    }
+
+    // One frame, `count` times over
+    fn write(&mut self, pixels: &[u32], width: u32, height: u32, count: u64) -> io::Result<()> {
+        let (stream_width, stream_height) = *self.size.get_or_insert((width, height));
+        match &mut self.output {
+            Output::Png(directory) => {
+                let rgba = export::to_rgba(&opaque(pixels));
+                let png = export::encode_png(width, height, &rgba).map_err(io::Error::other)?;
+                for _ in 0..count {
+                    self.written += 1;
+                    let path = directory.join(format!("frame-{:06}.png", self.written));
+                    fs::write(path, &png)?;
+                }
+            }
+            Output::Y4m(out) => {
+                if self.written == 0 {
+                    out.write_all(y4m_header(stream_width, stream_height, self.fps).as_bytes())?;
+                }
+                let pixels = fit(pixels, (width, height), (stream_width, stream_height));
+                let frame = to_yuv444(&pixels);
+                for _ in 0..count {
+                    self.written += 1;
+                    out.write_all(b"FRAME\n")?;
+                    out.write_all(&frame)?;
+                }
+            }
+            Output::Raw(out) => {
+                if self.written == 0 {
+                    eprintln!(
+                        "Writing {stream_width}x{stream_height} RGBA frames at {} fps",
+                        self.fps
+                    );
+                }
+                let pixels = fit(pixels, (width, height), (stream_width, stream_height));
+                let frame = export::to_rgba(&opaque(&pixels));
+                for _ in 0..count {
+                    self.written += 1;
+                    out.write_all(&frame)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The window leaves the alpha byte undefined
+fn opaque(pixels: &[u32]) -> Vec<u32> {
+    pixels.iter().map(|pixel| pixel | 0xFF000000).collect()
+}
+
+// Progressive frames, square pixels, no chroma subsampling
+fn y4m_header(width: u32, height: u32, fps: u32) -> String {
+    format!("YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C444\n")
+}
+
+// Streams can't change size, a resized window is cropped or padded with black
+// at the bottom and right. So is a buffer shorter than its size says
+fn fit(pixels: &[u32], (width, height): (u32, u32), (to_width, to_height): (u32, u32)) -> Vec<u32> {
+    let length = to_width as usize * to_height as usize;
+    if (width, height) == (to_width, to_height) && pixels.len() == length {
+        return pixels.to_vec();
+    }
+    let mut fitted = vec![0; length];
+    let columns = width.min(to_width) as usize;
+    for y in 0..height.min(to_height) as usize {
+        let from = y * width as usize;
+        let to = y * to_width as usize;
+        let Some(row) = pixels.get(from..from + columns) else {
+            break;
+        };
+        fitted[to..to + columns].copy_from_slice(row);
+    }
+    fitted
+}
+
+// Planar 4:4:4 with BT.601 studio range, what y4m readers assume by default
+fn to_yuv444(pixels: &[u32]) -> Vec<u8> {
+    let plane = pixels.len();
+    let mut yuv = vec![0; plane * 3];
+    for (index, &pixel) in pixels.iter().enumerate() {
+        let r = ((pixel >> 16) & 0xFF) as i32;
+        let g = ((pixel >> 8) & 0xFF) as i32;
+        let b = (pixel & 0xFF) as i32;
+        yuv[index] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        yuv[plane + index] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        yuv[2 * plane + index] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    yuv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2, numbered from 1 so black padding stands out
+    const PIXELS: [u32; 6] = [1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn crops_and_pads() {
+        assert_eq!(fit(&PIXELS, (3, 2), (3, 2)), PIXELS);
+        assert_eq!(fit(&PIXELS, (3, 2), (2, 1)), [1, 2]);
+        assert_eq!(
+            fit(&PIXELS, (3, 2), (4, 3)),
+            [1, 2, 3, 0, 4, 5, 6, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(fit(&PIXELS, (3, 2), (2, 3)), [1, 2, 4, 5, 0, 0]);
+    }
+
+    #[test]
+    fn pads_short_buffers() {
+        assert_eq!(fit(&PIXELS[..4], (3, 2), (3, 2)), [1, 2, 3, 0, 0, 0]);
+        assert_eq!(fit(&[], (3, 2), (2, 2)), [0; 4]);
+    }
+
+    #[test]
+    fn converts_to_studio_range() {
+        // Planes one after the other: Y, then U, then V
+        let yuv = to_yuv444(&[0xFF000000, 0xFFFFFFFF]);
+        assert_eq!(yuv, [16, 235, 128, 128, 128, 128]);
+        // Pure red, the BT.601 textbook values
+        assert_eq!(to_yuv444(&[0xFFFF0000]), [82, 90, 240]);
+    }
+
+    #[test]
+    fn writes_y4m_headers() {
+        assert_eq!(
+            y4m_header(1280, 720, 30),
+            "YUV4MPEG2 W1280 H720 F30:1 Ip A1:1 C444\n"
+        );
+    }
+}
@@ -0,0 +1,319 @@
+// Subcommands that render sessions without opening a window
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    animation::{self, Frame},
+    background::Background,
+    blend,
+    document::{Element, Stroke},
+    export, raster,
+    recording::Replay,
+    session::{Session, SessionError},
+    svg,
+    view::View,
+};
+
+// How long a time-lapse lingers on the finished drawing before looping
+const TIMELAPSE_HOLD: Duration = Duration::from_secs(2);
+
+// Largest output in pixels, a 256 MB buffer per frame
+const MAX_PIXELS: usize = 1 << 26;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Svg,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Svg => "svg",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "apng",
+        }
+    }
+}
+
+// `render FILE.dos -o OUT.png`: the current page, framed the way it was on
+// screen when the session was saved and `scale` times the window size. PNGs
+// come from the same rasterizer the window uses, so at scale 1 they match
+// what was on screen pixel for pixel
+pub fn render(
+    input: &Path,
+    output: &Path,
+    format: Format,
+    scale: f32,
+) -> Result<(), Box<dyn Error>> {
+    let session = Session::load(input)?;
+    let page = &session.pages[session.current_page];
+    let (width, height) = output_size(&session, scale)?;
+    match format {
+        Format::Png => {
+            let view = View {
+                zoom: page.view.zoom * scale,
+                ..page.view
+            };
+            let pixels = render_page(&page.elements, view, &session.background, width, height);
+            export::save_png(output, width, height, &export::to_rgba(&pixels))?;
+        }
+        Format::Svg => {
+            let (x, y) = page.view.to_world((0, 0));
+            let viewport = (
+                x,
+                y,
+                session.width as f32 / page.view.zoom,
+                session.height as f32 / page.view.zoom,
+            );
+            let svg = svg::to_svg(
+                &page.elements,
+                viewport,
+                (width, height),
+                &session.background,
+            );
+            fs::write(output, svg)?;
+        }
+    }
+    println!("Saved {}", output.display());
+    Ok(())
+}
+
+// `timelapse FILE.dos -o OUT.gif`: the current page being drawn from nothing,
+// `fps` frames a second for `duration` seconds. The strokes are laid down point
+// by point in the order they were drawn, images appear all at once
+pub fn timelapse(
+    input: &Path,
+    output: &Path,
+    format: AnimationFormat,
+    fps: u32,
+    duration: f32,
+    scale: f32,
+) -> Result<(), Box<dyn Error>> {
+    // A recording is input for the window's handlers and doesn't hold the page
+    // it was drawn on, so only a window can play it back
+    let session = match Session::load(input) {
+        Err(SessionError::NotASession) if Replay::load(input, 1.0).is_ok() => {
+            return Err(
+                "recordings play back in the window, use --replay with --frames to get their frames"
+                    .into(),
+            );
+        }
+        session => session?,
+    };
+    let page = &session.pages[session.current_page];
+    let (width, height) = output_size(&session, scale)?;
+    let view = View {
+        zoom: page.view.zoom * scale,
+        ..page.view
+    };
+    let render =
+        |elements: &[Element]| render_page(elements, view, &session.background, width, height);
+
+    // Frame `index` shows its share of all the points, the first one none
+    let frame_count = ((fps as f32 * duration).round() as u32).max(2);
+    let total = page.elements.iter().map(progress_units).sum::<usize>();
+    let frame_delay = Duration::from_secs_f32(1.0 / fps as f32);
+    let frames = (0..frame_count).map(|index| {
+        let shown = total * index as usize / (frame_count - 1) as usize;
+        let is_last = index == frame_count - 1;
+        Frame {
+            pixels: render(&partial_elements(&page.elements, shown)),
+            delay: if is_last {
+                frame_delay + TIMELAPSE_HOLD
+            } else {
+                frame_delay
+            },
+        }
+    });
+
+    let out = BufWriter::new(File::create(output)?);
+    match format {
+        AnimationFormat::Gif => {
+            let first = render(&[]);
+            let last = render(&page.elements);
+            animation::write_gif(out, width, height, &[&first, &last], frames)?;
+        }
+        AnimationFormat::Apng => animation::write_apng(out, width, height, frame_count, frames)?,
+    }
+    println!("Saved {}", output.display());
+    Ok(())
+}
+
+// The session's window size times `scale`, refused when it gets too large to
+// hold in memory
+fn output_size(session: &Session, scale: f32) -> Result<(u32, u32), Box<dyn Error>> {
+    let width = (session.width as f32 * scale).round().max(1.0) as u32;
+    let height = (session.height as f32 * scale).round().max(1.0) as u32;
+    match (width as usize).checked_mul(height as usize) {
+        Some(pixels) if pixels <= MAX_PIXELS => Ok((width, height)),
+        _ => Err(format!("{width}x{height} is too large to render, try a smaller --scale").into()),
+    }
+}
+
+// A stroke takes one step per point, an image one step
+fn progress_units(element: &Element) -> usize {
+    match element {
+        Element::Stroke(stroke) => stroke.points.len().max(1),
+        Element::Image { .. } => 1,
+    }
+}
+
+// The first `units` steps of `elements`, the last stroke possibly cut short
+fn partial_elements(elements: &[Element], mut units: usize) -> Vec<Element> {
+    let mut partial = Vec::new();
+    for element in elements {
+        if units == 0 {
+            break;
+        }
+        let element_units = progress_units(element);
+        match element {
+            Element::Stroke(stroke) if units < element_units => {
+                partial.push(Element::Stroke(Stroke {
+                    color: stroke.color,
+                    radius: stroke.radius,
+                    is_eraser: stroke.is_eraser,
+                    points: stroke.points[..units].to_vec(),
+                    id: stroke.id,
+                }))
+            }
+            _ => partial.push(element.clone()),
+        }
+        units = units.saturating_sub(element_units);
+    }
+    partial
+}
+
+// Background and drawing as seen through `view` in a `width` x `height` window
+pub fn render_page(
+    elements: &[Element],
+    view: View,
+    background: &Background,
+    width: u32,
+    height: u32,
+) -> Vec<u32> {
+    let mut pixels = vec![0; width as usize * height as usize];
+    background.render(
+        &mut pixels,
+        width,
+        (0, 0, width, height),
+        view.origin(),
+        view.zoom,
+        false,
+    );
+    blend::composite(&mut pixels, &raster::render(elements, &view, width, height));
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Arc};
+
+    use super::*;
+    use crate::{background::Pattern, document::Page, image::Image, tiles::TiledCanvas};
+
+    fn session() -> Session {
+        let stroke = |color, is_eraser, points: &[(f32, f32)]| {
+            Element::Stroke(Stroke {
+                color,
+                radius: 3.5,
+                is_eraser,
+                points: points.to_vec(),
+                id: None,
+            })
+        };
+        Session {
+            width: 96,
+            height: 64,
+            pointer_color: 0x00ef4444,
+            draw_brush_radius: 2,
+            erase_brush_radius: 7,
+            palette: Vec::new(),
+            background: Background {
+                pattern: Pattern::Grid,
+                color: 0x00f8fafc,
+                spacing: 20.0,
+                image: None,
+            },
+            pages: vec![Page {
+                elements: vec![
+                    Element::Image {
+                        x: 4.0,
+                        y: 6.0,
+                        width: 30.0,
+                        height: 20.0,
+                        image: Arc::new(Image::from_rgba(2, 2, &[0x80; 16])),
+                    },
+                    stroke(0x003b82f6, false, &[(2.0, 3.0), (40.5, 30.25), (80.0, 5.0)]),
+                    stroke(0, true, &[(20.0, 20.0), (30.0, 24.0)]),
+                ],
+                view: View {
+                    offset_x: 7.0,
+                    offset_y: -5.0,
+                    zoom: 1.5,
+                },
+                ..Page::default()
+            }],
+            current_page: 0,
+        }
+    }
+
+    #[test]
+    fn renders_what_the_window_shows() {
+        let session = session();
+        let directory = env::temp_dir().join(format!("drawonscreen-render-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("session.dos");
+        let output = directory.join("session.png");
+        session.save(&input).unwrap();
+        render(&input, &output, Format::Png, 1.0).unwrap();
+        let rendered = Image::decode_png(&fs::read(&output).unwrap()).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        // The way the window composes a frame: background, then the tiles
+        let page = &session.pages[0];
+        let (width, height) = (session.width, session.height);
+        let origin = page.view.origin();
+        let mut canvas = TiledCanvas::new(page.view.zoom);
+        canvas.prepare(&page.elements, (origin.0, origin.1, width, height));
+        let mut pixels = vec![0; (width * height) as usize];
+        let rect = (0, 0, width, height);
+        session
+            .background
+            .render(&mut pixels, width, rect, origin, page.view.zoom, false);
+        canvas.composite_onto(&mut pixels, width, rect, origin);
+
+        assert_eq!((rendered.width, rendered.height), (width, height));
+        assert_eq!(export::to_rgba(&rendered.pixels), export::to_rgba(&pixels));
+    }
+
+    #[test]
+    fn refuses_huge_outputs() {
+        let session = Session {
+            width: 4096,
+            height: 4096,
+            ..session()
+        };
+        assert!(output_size(&session, 16.0).is_err());
+        assert_eq!(output_size(&session, 1.0).unwrap(), (4096, 4096));
+    }
+}
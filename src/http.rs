@@ -0,0 +1,195 @@
+// A small HTTP server on localhost for embedding the canvas elsewhere, like an
+// OBS browser source or a web page:
+//
+//   GET /canvas.png   what the window shows without overlays, transparent
+//                     where the background is
+//   GET /events       server-sent events, an `update` whenever the window
+//                     showed something new, with a counter as data
+//
+// The image is rendered on the event loop like the control socket's commands
+// and encoded here. Only built with the `http` feature
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::{control::UserEvent, export};
+
+// Proxies close connections that stay quiet for too long
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// Browsers don't need more updates than this, a burst of them comes out as one
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+
+// The canvas, as asked for by a `/canvas.png` request
+pub struct CanvasImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Updates {
+    count: Mutex<u64>,
+    changed: Condvar,
+}
+
+// Stops nothing when dropped, the threads just stay blocked until exit
+pub struct HttpServer {
+    updates: Arc<Updates>,
+}
+
+impl HttpServer {
+    // The window showed something new, for `/events`
+    pub fn changed(&self) {
+        let mut count = self
+            .updates
+            .count
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        *count += 1;
+        self.updates.changed.notify_all();
+    }
+}
+
+// Listen on localhost only, the canvas is nobody else's business
+pub fn serve(port: u16, proxy: EventLoopProxy<UserEvent>) -> io::Result<HttpServer> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let updates = Arc::new(Updates::default());
+    {
+        let updates = updates.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let updates = updates.clone();
+                let proxy = proxy.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream, &updates, &proxy); // The client went away
+                });
+            }
+        });
+    }
+    Ok(HttpServer { updates })
+}
+
+fn handle(
+    mut stream: TcpStream,
+    updates: &Updates,
+    proxy: &EventLoopProxy<UserEvent>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Nothing in the headers matters
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(
+            &mut stream,
+            "400 Bad Request",
+            "text/plain",
+            b"Bad request\n",
+        );
+    };
+    let path = target.split('?').next().unwrap_or_default();
+    if method != "GET" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Only GET\n",
+        );
+    }
+    match path {
+        "/canvas.png" => match canvas_png(proxy) {
+            Some(png) => respond(&mut stream, "200 OK", "image/png", &png),
+            None => respond(
+                &mut stream,
+                "503 Service Unavailable",
+                "text/plain",
+                b"No canvas to show\n",
+            ),
+        },
+        "/events" => events(&mut stream, updates),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found\n"),
+    }
+}
+
+// `None` before the window exists or once it is gone
+fn canvas_png(proxy: &EventLoopProxy<UserEvent>) -> Option<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    proxy.send_event(UserEvent::Canvas(sender)).ok()?;
+    let image = receiver.recv().ok()?;
+    if image.width == 0 || image.height == 0 {
+        return None;
+    }
+    match export::encode_png(image.width, image.height, &image.rgba) {
+        Ok(png) => Some(png),
+        Err(err) => {
+            eprintln!("Failed to encode the canvas for HTTP: {err}");
+            None
+        }
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// Runs until the client disconnects, which shows up as a failed write
+fn events(stream: &mut TcpStream, updates: &Updates) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Cache-Control: no-store\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: keep-alive\r\n\r\n"
+    )?;
+    let mut seen = *updates.count.lock().unwrap_or_else(|err| err.into_inner());
+    write!(stream, "event: update\ndata: {seen}\n\n")?;
+    stream.flush()?;
+
+    loop {
+        let count = {
+            let count = updates.count.lock().unwrap_or_else(|err| err.into_inner());
+            let (count, _) = updates
+                .changed
+                .wait_timeout_while(count, KEEP_ALIVE, |count| *count == seen)
+                .unwrap_or_else(|err| err.into_inner());
+            *count
+        };
+        if count == seen {
+            write!(stream, ": keep-alive\n\n")?;
+        } else {
+            seen = count;
+            write!(stream, "event: update\ndata: {seen}\n\n")?;
+        }
+        stream.flush()?;
+        thread::sleep(MIN_UPDATE_INTERVAL);
+    }
+}
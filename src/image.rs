@@ -0,0 +1,158 @@
+use std::{io::Cursor, sync::Arc};
+
+use crate::{blend, document::Element, view::View};
+
+// RGBA rasters that come from outside the canvas (clipboard, files, ...)
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>, // 0xAARRGGBB, straight (non-premultiplied) alpha
+}
+
+impl Image {
+    pub fn from_rgba(width: u32, height: u32, bytes: &[u8]) -> Self {
+        let pixels = bytes
+            .chunks_exact(4)
+            .map(|p| {
+                ((p[3] as u32) << 24) | ((p[0] as u32) << 16) | ((p[1] as u32) << 8) | p[2] as u32
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn decode_png(bytes: &[u8]) -> Result<Self, png::DecodingError> {
+        let mut decoder = png::Decoder::new(Cursor::new(bytes));
+        // Expand palettes, grayscale and 16 bit channels to plain 8 bit samples
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap_or_default()];
+        let info = reader.next_frame(&mut buffer)?;
+        let samples = &buffer[..info.buffer_size()];
+
+        let rgba: Vec<u8> = match info.color_type {
+            png::ColorType::Rgba => samples.to_vec(),
+            png::ColorType::Rgb => samples
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => samples
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => samples.iter().flat_map(|&v| [v, v, v, 0xFF]).collect(),
+            png::ColorType::Indexed => unreachable!("palettes are expanded by the decoder"),
+        };
+
+        Ok(Self::from_rgba(info.width, info.height, &rgba))
+    }
+}
+
+// Blend `image`, stretched over the `(x, y, width, height)` rectangle `placement`,
+// into `target`, a `width` x `height` buffer
+pub fn draw_scaled(
+    image: &Image,
+    placement: (i32, i32, u32, u32),
+    target: &mut [u32],
+    width: u32,
+    height: u32,
+) {
+    let (x, y, scaled_width, scaled_height) = placement;
+
+    let start_x = x.max(0);
+    let end_x = (x + scaled_width as i32).min(width as i32);
+    let start_y = y.max(0);
+    let end_y = (y + scaled_height as i32).min(height as i32);
+
+    let scale_x = image.width as f32 / scaled_width as f32;
+    let scale_y = image.height as f32 / scaled_height as f32;
+
+    for target_y in start_y..end_y {
+        // Nearest neighbour sampling
+        let src_y = (((target_y - y) as f32 * scale_y) as u32).min(image.height - 1);
+        for target_x in start_x..end_x {
+            let src_x = (((target_x - x) as f32 * scale_x) as u32).min(image.width - 1);
+            let src = image.pixels[(src_y * image.width + src_x) as usize];
+
+            let alpha = (src >> 24) as f32 / 255.0;
+            if alpha > 0.0 {
+                let idx = (target_y as u32 * width + target_x as u32) as usize;
+                target[idx] = blend::source_over_pixel(target[idx], src, alpha);
+            }
+        }
+    }
+}
+
+// An image hovering above the canvas that can be moved and scaled before it is
+// committed into the pixels
+pub struct FloatingImage {
+    image: Arc<Image>,
+    x: i32, // Top-left corner in canvas coordinates
+    y: i32,
+    scale: f32,
+}
+
+impl FloatingImage {
+    pub fn new(image: Image, center: (i32, i32)) -> Self {
+        let x = center.0 - image.width as i32 / 2;
+        let y = center.1 - image.height as i32 / 2;
+
+        Self {
+            image: Arc::new(image),
+            x,
+            y,
+            scale: 1.0,
+        }
+    }
+
+    fn scaled_size(&self) -> (u32, u32) {
+        let width = (self.image.width as f32 * self.scale).round().max(1.0) as u32;
+        let height = (self.image.height as f32 * self.scale).round().max(1.0) as u32;
+        (width, height)
+    }
+
+    pub fn move_by(&mut self, dx: i32, dy: i32) {
+        self.x += dx;
+        self.y += dy;
+    }
+
+    // Scale around the center so the image doesn't drift while resizing
+    pub fn scale_by(&mut self, factor: f32) {
+        let (old_width, old_height) = self.scaled_size();
+        self.scale = (self.scale * factor).clamp(0.05, 20.0);
+        let (new_width, new_height) = self.scaled_size();
+
+        self.x -= (new_width as i32 - old_width as i32) / 2;
+        self.y -= (new_height as i32 - old_height as i32) / 2;
+    }
+
+    pub fn composite(&self, target: &mut [u32], width: u32, height: u32) {
+        let (scaled_width, scaled_height) = self.scaled_size();
+        draw_scaled(
+            &self.image,
+            (self.x, self.y, scaled_width, scaled_height),
+            target,
+            width,
+            height,
+        );
+    }
+
+    // Freeze the current placement, in world coordinates, so it can be kept in
+    // the document
+    pub fn to_element(&self, view: &View) -> Element {
+        let (width, height) = self.scaled_size();
+        let (x, y) = view.to_world((self.x, self.y));
+        Element::Image {
+            x,
+            y,
+            width: width as f32 / view.zoom,
+            height: height as f32 / view.zoom,
+            image: self.image.clone(),
+        }
+    }
+}
@@ -1,15 +1,84 @@
-use std::{error::Error, num::NonZeroU32, rc::Rc};
+mod animation;
+mod background;
+mod blend;
+mod capture;
+mod cli;
+mod clipboard;
+mod config;
+mod control;
+mod dbus;
+mod document;
+mod export;
+mod frames;
+mod headless;
+#[cfg(feature = "http")]
+mod http;
+mod image;
+mod overlay;
+mod parallel;
+mod pdf;
+mod raster;
+mod recording;
+mod selection;
+mod session;
+mod shared;
+mod svg;
+mod tiles;
+mod view;
+
+use std::{
+    env,
+    error::Error,
+    mem,
+    net::{Ipv4Addr, TcpListener},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use arboard::Clipboard;
 use softbuffer::{Context, Rect, Surface};
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
     event::{ElementState, MouseButton, WindowEvent},
-    event_loop::{ActiveEventLoop, EventLoop},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy},
     keyboard::{KeyCode, PhysicalKey},
-    window::{CursorIcon, Window, WindowId},
+    raw_window_handle::{HasWindowHandle, RawWindowHandle},
+    window::{CursorIcon, Fullscreen, Window, WindowId},
+};
+
+#[cfg(feature = "http")]
+use crate::http::{CanvasImage, HttpServer};
+use crate::{
+    background::Background,
+    capture::Screenshot,
+    config::{BackgroundSpec, Config},
+    control::{ControlSocket, Reply, Request, Tool, UserEvent},
+    dbus::DbusService,
+    document::{Element, Page, Snapshot, Stroke, StrokeId},
+    frames::{FrameDump, FrameTarget},
+    image::{FloatingImage, Image},
+    pdf::PageContent,
+    recording::{Action, Header, InputEvent, Recorder, Replay},
+    selection::Selection,
+    session::Session,
+    shared::{Message, PeerEvent, SharedBackground, SharedPage, SharedStroke, Sharing},
+    tiles::TiledCanvas,
+    view::View,
 };
 
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Share of the screen an opened image may take up before it is scaled down
+const IMAGE_FIT: f32 = 0.9;
+
+// Undo steps kept per page. Each one holds a copy of the page's elements, so the
+// oldest go once there are more
+const MAX_UNDO_STEPS: usize = 100;
+
 #[derive(Clone, Copy)]
 enum Color {
     Red = 0x00ef4444,
@@ -24,6 +93,9 @@ enum DrawState {
     Idle,
     Drawing,
     Erasing,
+    Moving,    // Dragging a pasted image around
+    Selecting, // Shift + drag to pick a region
+    Panning,   // Middle or Space + left drag moves the view
 }
 
 struct DrawOnScreen {
@@ -31,319 +103,1463 @@ struct DrawOnScreen {
     context: Option<Context<Rc<Window>>>,
     surface: Option<Surface<Rc<Window>, Rc<Window>>>,
 
-    pixels: Vec<u32>, // This will hold the current state of your drawing
+    canvas: TiledCanvas,    // The document rasterized at the zoom of `view`
+    background: Background, // Drawn under the canvas, shared by all pages
+    elements: Vec<Element>, // Strokes and images in world coordinates
+    view: View,
+    pages: Vec<Page>, // Whiteboard pages, the current one is swapped out into the fields above
+    current_page: usize,
     inner_size: PhysicalSize<u32>,
     position: Option<(i32, i32)>,
     last_position: Option<(i32, i32)>,
 
-    pointer_color: Color,
+    pointer_color: u32,
+    tool: Tool,        // What the left button does, the right one always erases
+    palette: Vec<u32>, // Colors behind the 1, 2, 3 and 0 keys
     draw_state: DrawState,
     draw_brush_radius: i32,
     erase_brush_radius: i32,
 
     is_control_key_pressed: bool,
-
-    undo_stack: Vec<Vec<u32>>,
-    redo_stack: Vec<Vec<u32>>,
+    is_shift_key_pressed: bool,
+    is_space_key_pressed: bool,
+    is_snapping: bool,               // Stroke points snap to grid intersections
+    snap_marker: Option<(i32, i32)>, // Where the snap marker is on screen right now
+
+    floating_image: Option<FloatingImage>, // Pasted image waiting to be committed
+    selection: Option<Selection>,
+    clipboard: Option<Clipboard>, // For pasting, created the first time
+
+    undo_stack: Vec<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+
+    session_path: Option<PathBuf>, // Where Ctrl+Shift+S writes the session
+    output_dir: Option<PathBuf>,   // Where exports go, ~/Pictures when not set
+    fullscreen: bool,
+    monitor: Option<usize>, // Index into the monitors the display server lists
+    pending_session: Option<Session>, // Loaded on startup, applied once the window exists
+    pending_screenshot: Option<Screenshot>, // Freeze mode, becomes the background once the window exists
+    pending_image: Option<Image>,           // Opened from the command line, likewise
+    is_dirty: bool,                         // Changed since the last autosave
+    next_autosave: Instant,
+
+    is_hidden: bool, // Hidden through the control socket or D-Bus
+    control_socket: Option<ControlSocket>,
+    dbus: Option<DbusService>,
+    recorder: Option<Recorder>, // Writing the input to a file
+    replay: Option<Replay>,     // Feeding input from a file instead
+    frames: Option<FrameDump>,  // Gets a copy of everything presented
+    #[cfg(feature = "http")]
+    http: Option<HttpServer>, // Told about everything presented
+    sharing: Option<Sharing>,   // Drawing together with other instances
+    pending_page: Option<SharedPage>, // The host's page, taken over once the window exists
+    tag_rects: Vec<(u32, u32, u32, u32)>, // Where the name tags of other peers are on screen
+    needs_repaint: bool,        // Something changed that `present_dirty` doesn't track
+    last_damage: Option<Vec<(u32, u32, u32, u32)>>, // What the last present changed, `None` for everything
 }
 
 impl DrawOnScreen {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            elements: self.elements.clone(),
+            tiles: self.canvas.snapshot(),
+        }
+    }
+
     fn save_state(&mut self) {
-        // Save the current `self.pixels` state to undo stack
-        self.undo_stack.push(self.pixels.clone());
+        // Save the current document to the undo stack
+        self.undo_stack.push(self.snapshot());
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
         self.redo_stack.clear(); // Clear redo after new action
+        self.is_dirty = true;
+    }
+
+    fn restore_state(&mut self, snapshot: Snapshot) {
+        self.elements = snapshot.elements;
+        self.is_dirty = true;
+        if self.canvas.restore(snapshot.tiles) {
+            self.request_repaint();
+        } else {
+            self.invalidate_canvas();
+        }
+    }
+
+    // Drop every rendered tile, after anything that changes more than a stroke
+    // can touch (zoom, page switch, clearing). They are rendered again from
+    // `elements` as they come into view
+    fn invalidate_canvas(&mut self) {
+        self.canvas = TiledCanvas::new(self.view.zoom);
+        self.request_repaint();
+    }
+
+    // Paint the whole window again on the next redraw. Redraws that come from
+    // the display server alone present the buffer as it is
+    fn request_repaint(&mut self) {
+        self.needs_repaint = true;
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
+    }
+
+    // The background is its own layer, so changing it never touches the tiles
+    fn background_changed(&mut self) {
+        if let Some(sharing) = &self.sharing
+            && !sharing.read_only
+        {
+            sharing.send(&Message::Background(SharedBackground::from_background(
+                &self.background,
+            )));
+        }
+        self.is_dirty = true;
+        self.request_repaint();
     }
 
-    fn restore_state(&mut self, pixels: Vec<u32>) {
-        if self.pixels.len() == pixels.len() {
-            self.pixels.copy_from_slice(&pixels);
-            // Request redraw after restoring state
-            if let Some(window) = &self.window {
-                window.request_redraw();
+    // Make sure everything inside the window has been rendered
+    fn prepare_canvas(&mut self) {
+        let (x, y) = self.view.origin();
+        self.canvas.prepare(
+            &self.elements,
+            (x, y, self.inner_size.width, self.inner_size.height),
+        );
+    }
+
+    fn to_session(&self) -> Session {
+        Session {
+            width: self.inner_size.width,
+            height: self.inner_size.height,
+            pointer_color: self.pointer_color,
+            draw_brush_radius: self.draw_brush_radius,
+            erase_brush_radius: self.erase_brush_radius,
+            palette: self.palette.clone(),
+            background: self.background.clone(),
+            pages: self
+                .pages
+                .iter()
+                .enumerate()
+                .map(|(index, page)| {
+                    let (elements, view) = if index == self.current_page {
+                        (&self.elements, self.view)
+                    } else {
+                        (&page.elements, page.view)
+                    };
+                    Page {
+                        elements: elements.clone(),
+                        view,
+                        ..Page::default()
+                    }
+                })
+                .collect(),
+            current_page: self.current_page,
+        }
+    }
+
+    fn apply_session(&mut self, session: Session) {
+        self.pages = session.pages;
+
+        // Bring the page that was open when saving on screen
+        let current = mem::take(&mut self.pages[session.current_page]);
+        self.elements = current.elements;
+        self.view = current.view;
+        self.current_page = session.current_page;
+
+        self.pointer_color = session.pointer_color;
+        self.draw_brush_radius = session.draw_brush_radius.clamp(1, 20);
+        self.erase_brush_radius = session.erase_brush_radius.clamp(1, 50);
+        if !session.palette.is_empty() {
+            self.palette = session.palette;
+        }
+        self.background = session.background;
+
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.invalidate_canvas();
+    }
+
+    fn save_session(&mut self) {
+        let path = self
+            .session_path
+            .get_or_insert_with(|| {
+                export::default_png_path(self.output_dir.as_deref()).with_extension("dos")
+            })
+            .clone();
+        match self.to_session().save(&path) {
+            Ok(()) => self.saved(&path),
+            Err(err) => eprintln!("Failed to save {}: {err}", path.display()),
+        }
+    }
+
+    fn autosave(&mut self) {
+        if !self.is_dirty {
+            return;
+        }
+        let Some(path) = session::autosave_path() else {
+            return;
+        };
+        match self.to_session().save(&path) {
+            Ok(()) => self.is_dirty = false,
+            Err(err) => eprintln!("Autosave to {} failed: {err}", path.display()),
+        }
+    }
+
+    // What is on screen, or the selected part of it
+    fn export_svg(&self) {
+        let PhysicalSize { width, height } = self.inner_size;
+        let (x, y, width, height) = self
+            .selection
+            .as_ref()
+            .and_then(|selection| selection.rect(width, height))
+            .unwrap_or((0, 0, width, height));
+
+        let (world_x, world_y) = self.view.to_world((x as i32, y as i32));
+        let viewport = (
+            world_x,
+            world_y,
+            width as f32 / self.view.zoom,
+            height as f32 / self.view.zoom,
+        );
+        let svg = svg::to_svg(&self.elements, viewport, (width, height), &self.background);
+        let path = export::default_png_path(self.output_dir.as_deref()).with_extension("svg");
+        match std::fs::write(&path, svg) {
+            Ok(()) => self.saved(&path),
+            Err(err) => eprintln!("Failed to save {}: {err}", path.display()),
+        }
+    }
+
+    // Every page into one PDF, each framed the way it was last seen on screen
+    fn export_pdf(&self) {
+        let PhysicalSize { width, height } = self.inner_size;
+        let pages: Vec<PageContent> = self
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(index, page)| {
+                let (elements, view) = if index == self.current_page {
+                    (&self.elements[..], self.view)
+                } else {
+                    (&page.elements[..], page.view)
+                };
+                if pdf::can_draw_vector(elements, &self.background) {
+                    PageContent::Vector { elements, view }
+                } else {
+                    PageContent::Raster(headless::render_page(
+                        elements,
+                        view,
+                        &self.background,
+                        width,
+                        height,
+                    ))
+                }
+            })
+            .collect();
+
+        let pdf = pdf::write_pdf(&pages, width, height, self.background.fill_color());
+        let path = export::default_png_path(self.output_dir.as_deref()).with_extension("pdf");
+        match std::fs::write(&path, pdf) {
+            Ok(()) => self.saved(&path),
+            Err(err) => eprintln!("Failed to save {}: {err}", path.display()),
+        }
+    }
+
+    // Undo point plus a new stroke in `elements`. `position` is on screen, the
+    // stroke keeps it and the brush size in world units so it stays put when the
+    // view changes
+    fn begin_stroke(&mut self, position: (i32, i32), color: u32, is_eraser: bool) {
+        self.save_state();
+        let radius = if is_eraser {
+            self.erase_brush_radius
+        } else {
+            self.draw_brush_radius
+        };
+        let point = self.stroke_point(position, is_eraser);
+        let stroke = Stroke {
+            color,
+            radius: radius as f32 / self.view.zoom,
+            is_eraser,
+            points: vec![point],
+            id: self.sharing.as_mut().map(Sharing::next_id),
+        };
+        if let Some(sharing) = &self.sharing
+            && let Some(stroke) = SharedStroke::from_stroke(&stroke)
+        {
+            sharing.send(&Message::Begin { stroke });
+        }
+        self.elements.push(Element::Stroke(stroke));
+    }
+
+    // Returns false when the point adds nothing, which happens while snapping
+    // and the cursor hasn't reached another grid intersection
+    fn extend_stroke(&mut self, position: (i32, i32)) -> bool {
+        let Some(Element::Stroke(stroke)) = self.elements.last() else {
+            return false;
+        };
+        let point = self.stroke_point(position, stroke.is_eraser);
+        if stroke.points.last() == Some(&point) {
+            return false;
+        }
+        if let Some(Element::Stroke(stroke)) = self.elements.last_mut() {
+            stroke.points.push(point);
+            if let (Some(sharing), Some(id)) = (&self.sharing, stroke.id) {
+                sharing.send(&Message::Extend {
+                    id,
+                    points: vec![point],
+                });
             }
         }
+        true
     }
 
-    fn xy_to_index(&self, x: u32, y: u32, width: u32) -> usize {
-        (y * width + x) as usize
+    // The world point a screen position adds to a stroke, snapped to the grid
+    // when snapping is on and the background has one. Erasing never snaps
+    fn stroke_point(&self, position: (i32, i32), is_eraser: bool) -> (f32, f32) {
+        let point = self.view.to_world(position);
+        if self.is_snapping && !is_eraser {
+            self.background.snap(point).unwrap_or(point)
+        } else {
+            point
+        }
     }
 
-    // Optimized Bresenham line algorithm - returns only the points, no allocation in hot path
-    fn bresenham_line_fast(
-        &self,
-        x0: i32,
-        y0: i32,
-        x1: i32,
-        y1: i32,
-        points: &mut Vec<(i32, i32)>,
-    ) {
-        // This function is currently empty in your provided code.
-        // The implementation for Bresenham is directly in draw_interpolated_line.
-        // If you intend to use bresenham_line_fast as a separate utility,
-        // its implementation should be moved here. For now, it's not strictly
-        // needed to fix the click drawing issue.
+    // Where the snap marker belongs on screen, if anywhere
+    fn snap_target(&self) -> Option<(i32, i32)> {
+        if !self.is_snapping
+            || self.draw_state == DrawState::Erasing
+            || self.draw_state == DrawState::Panning
+            || self.floating_image.is_some()
+        {
+            return None;
+        }
+        let point = self.background.snap(self.view.to_world(self.position?))?;
+        let (x, y) = view::to_canvas(point, self.view.zoom);
+        let (origin_x, origin_y) = self.view.origin();
+        Some((x - origin_x, y - origin_y))
     }
 
-    // Blend two colors with alpha blending
-    fn blend_colors(&self, background: u32, foreground: u32, alpha: f32) -> u32 {
-        let alpha = alpha.clamp(0.0, 1.0);
-        let inv_alpha = 1.0 - alpha;
+    // Rasterize the newest point of the stroke being drawn and show it
+    fn draw_stroke_tip(&mut self) {
+        let Some(Element::Stroke(stroke)) = self.elements.last() else {
+            return;
+        };
+        raster::draw_stroke_point(
+            &mut self.canvas,
+            self.view.zoom,
+            stroke,
+            stroke.points.len() - 1,
+        );
+        self.present_dirty();
+    }
+
+    fn clipboard(&mut self) -> Option<&mut Clipboard> {
+        if self.clipboard.is_none() {
+            match Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(err) => eprintln!("Clipboard is not available: {err}"),
+            }
+        }
+        self.clipboard.as_mut()
+    }
+
+    // The selected region, or the whole window, as RGBA bytes ready for export.
+    // With an image in the background and nothing selected it is the image,
+    // annotated, at its own resolution whatever the window shows of it
+    fn export_rgba(&mut self) -> (u32, u32, Vec<u8>) {
+        if self.selection.is_none()
+            && let Some(image) = &self.background.image
+        {
+            let (width, height) = (image.width, image.height);
+            let mut pixels = vec![0; (width * height) as usize];
+            self.background.render(
+                &mut pixels,
+                width,
+                (0, 0, width, height),
+                (0, 0),
+                1.0,
+                false,
+            );
+            blend::composite(
+                &mut pixels,
+                &raster::render(&self.elements, &View::default(), width, height),
+            );
+            return (width, height, export::to_rgba(&pixels));
+        }
+
+        let PhysicalSize { width, height } = self.inner_size;
+        let rect = self
+            .selection
+            .as_ref()
+            .and_then(|selection| selection.rect(width, height))
+            .unwrap_or((0, 0, width, height));
+        self.rect_rgba(rect)
+    }
+
+    // Everything the window shows but the overlays
+    #[cfg(feature = "http")]
+    fn window_rgba(&mut self) -> (u32, u32, Vec<u8>) {
+        let PhysicalSize { width, height } = self.inner_size;
+        self.rect_rgba((0, 0, width, height))
+    }
+
+    // A rect of the window, x and y from its top left
+    fn rect_rgba(&mut self, (x, y, width, height): (u32, u32, u32, u32)) -> (u32, u32, Vec<u8>) {
+        self.prepare_canvas();
+        let (origin_x, origin_y) = self.view.origin();
+        let origin = (origin_x + x as i32, origin_y + y as i32);
+        let rect = (0, 0, width, height);
+        let mut pixels = vec![0; (width * height) as usize];
+        // A transparent background stays transparent in the export
+        self.background
+            .render(&mut pixels, width, rect, origin, self.view.zoom, false);
+        self.canvas.composite_onto(&mut pixels, width, rect, origin);
+
+        (width, height, export::to_rgba(&pixels))
+    }
+
+    fn copy_to_clipboard(&mut self) {
+        let (width, height, rgba) = self.export_rgba();
+        if let Err(err) = clipboard::write_image(width, height, &rgba) {
+            eprintln!("Failed to copy: {err}");
+        }
+    }
+
+    // Report a finished save, also to D-Bus listeners
+    fn saved(&self, path: &Path) {
+        println!("Saved {}", path.display());
+        if let Some(dbus) = &self.dbus {
+            dbus.saved(path);
+        }
+    }
+
+    fn save_to_file(&mut self) -> Result<PathBuf, String> {
+        let (width, height, rgba) = self.export_rgba();
+        let path = export::default_png_path(self.output_dir.as_deref());
+        match export::save_png(&path, width, height, &rgba) {
+            Ok(()) => {
+                self.saved(&path);
+                Ok(path)
+            }
+            Err(err) => {
+                let message = format!("Failed to save {}: {err}", path.display());
+                eprintln!("{message}");
+                Err(message)
+            }
+        }
+    }
+
+    // Clear the current page. On a shared canvas only one's own strokes go
+    fn clear(&mut self) {
+        if self.sharing.is_some() {
+            let own = self.own_stroke_indices();
+            self.remove_own_strokes(&own);
+            return;
+        }
+        self.save_state();
+        self.elements.clear();
+        self.invalidate_canvas(); // Redraw to show the cleared screen
+    }
+
+    // On a shared canvas undo and redo go through one's own strokes, leaving
+    // everyone else's alone
+    fn undo(&mut self) {
+        if self.sharing.is_some() {
+            if let Some(&last) = self.own_stroke_indices().last() {
+                let removed = self.remove_own_strokes(&[last]);
+                if let Some(sharing) = &mut self.sharing {
+                    sharing.redo.extend(removed);
+                }
+            }
+            return;
+        }
+        if let Some(last) = self.undo_stack.pop() {
+            self.redo_stack.push(self.snapshot()); // Push current state to redo
+            self.restore_state(last);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(sharing) = &mut self.sharing {
+            if let Some(stroke) = sharing.redo.pop() {
+                if let Some(stroke) = SharedStroke::from_stroke(&stroke) {
+                    sharing.send(&Message::Begin { stroke });
+                }
+                self.add_shared_stroke(stroke);
+                self.is_dirty = true;
+            }
+            return;
+        }
+        if let Some(next) = self.redo_stack.pop() {
+            self.undo_stack.push(self.snapshot()); // Push current state to undo
+            self.restore_state(next);
+        }
+    }
 
-        let bg_r = ((background >> 16) & 0xFF) as f32;
-        let bg_g = ((background >> 8) & 0xFF) as f32;
-        let bg_b = (background & 0xFF) as f32;
+    fn set_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.draw_state = DrawState::Idle;
+        self.last_position = None;
+        self.tool_changed();
+    }
 
-        let fg_r = ((foreground >> 16) & 0xFF) as f32;
-        let fg_g = ((foreground >> 8) & 0xFF) as f32;
-        let fg_b = (foreground & 0xFF) as f32;
+    fn set_color(&mut self, color: u32) {
+        self.pointer_color = color;
+        self.tool_changed();
+    }
 
-        let r = (fg_r * alpha + bg_r * inv_alpha) as u32;
-        let g = (fg_g * alpha + bg_g * inv_alpha) as u32;
-        let b = (fg_b * alpha + bg_b * inv_alpha) as u32;
+    fn tool_changed(&self) {
+        if let Some(dbus) = &self.dbus {
+            dbus.tool_changed(self.tool, self.pointer_color);
+        }
+    }
+
+    // Hide the window without quitting, and bring it back
+    fn set_hidden(&mut self, is_hidden: bool) -> Result<(), String> {
+        let Some(window) = &self.window else {
+            self.is_hidden = is_hidden;
+            return Ok(());
+        };
+        if !is_wayland(window) {
+            self.is_hidden = is_hidden;
+            window.set_visible(!is_hidden);
+            return Ok(());
+        }
+
+        // Wayland windows can't be unmapped, so hiding minimizes instead. There
+        // is no taking that back from here either: asking for focus is all we
+        // can do, and compositors are free to ignore it
+        if is_hidden {
+            self.is_hidden = true;
+            window.set_minimized(true);
+            Ok(())
+        } else if self.is_hidden {
+            self.is_hidden = false;
+            window.set_minimized(false);
+            window.focus_window();
+            Err("Wayland can't restore a minimized window".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn toggle_visibility(&mut self) -> Result<(), String> {
+        self.set_hidden(!self.is_hidden)
+    }
 
-        0xFF000000 | (r << 16) | (g << 8) | b
+    fn quit(&mut self, event_loop: &ActiveEventLoop) {
+        self.autosave();
+        event_loop.exit();
     }
 
-    // Antialiased circle drawing with distance-based alpha
-    fn draw_circle_fast(&mut self, cx: i32, cy: i32, radius: i32, color: Color) {
-        let width = self.inner_size.width as i32;
-        let height = self.inner_size.height as i32;
+    // A command from the control socket or D-Bus
+    fn handle_request(&mut self, event_loop: &ActiveEventLoop, request: Request) -> Reply {
+        match request {
+            Request::Quit => {
+                self.quit(event_loop);
+                Reply::ok()
+            }
+            request => self.apply_request(request),
+        }
+    }
+
+    // Everything but quitting, which `handle_request` does as it needs the
+    // event loop
+    fn apply_request(&mut self, request: Request) -> Reply {
+        match request {
+            Request::SetColor { color } => match config::parse_color(&color) {
+                Ok(color) => self.set_color(color),
+                Err(err) => return Reply::error(err),
+            },
+            Request::SetTool { tool } => self.set_tool(tool),
+            Request::Clear => self.clear(),
+            Request::Undo => self.undo(),
+            Request::Save => {
+                return match self.save_to_file() {
+                    Ok(path) => Reply::saved(path.display().to_string()),
+                    Err(err) => Reply::error(err),
+                };
+            }
+            Request::Show => {
+                if let Err(err) = self.set_hidden(false) {
+                    return Reply::error(err);
+                }
+                if let Some(window) = &self.window {
+                    window.focus_window();
+                }
+            }
+            Request::Hide => {
+                if let Err(err) = self.set_hidden(true) {
+                    return Reply::error(err);
+                }
+            }
+            Request::ToggleVisibility => {
+                if let Err(err) = self.toggle_visibility() {
+                    return Reply::error(err);
+                }
+            }
+            Request::Quit => {}
+        }
+        Reply::ok()
+    }
+
+    fn paste_from_clipboard(&mut self) {
+        // Only strokes and the background go to the others
+        if self.sharing.is_some() {
+            eprintln!("Images can't be pasted while the canvas is shared");
+            return;
+        }
+        let Some(clipboard) = self.clipboard() else {
+            return;
+        };
+        match clipboard::read_image(clipboard) {
+            Ok(image) => {
+                // Drop the image under the cursor, or in the middle of the window
+                let center = self.position.unwrap_or((
+                    self.inner_size.width as i32 / 2,
+                    self.inner_size.height as i32 / 2,
+                ));
+                self.floating_image = Some(FloatingImage::new(image, center));
+                self.request_repaint();
+            }
+            Err(err) => eprintln!("Nothing to paste: {err}"),
+        }
+    }
+
+    fn commit_floating_image(&mut self) {
+        if let Some(floating_image) = self.floating_image.take() {
+            self.save_state();
+            let element = floating_image.to_element(&self.view);
+            raster::draw_element(&mut self.canvas, self.view.zoom, &element);
+            self.elements.push(element);
+            self.request_repaint();
+        }
+    }
+
+    fn switch_page(&mut self, index: usize) {
+        if index == self.current_page || index >= self.pages.len() {
+            return;
+        }
+        // Everyone draws on the page that was open when sharing started
+        if self.sharing.is_some() {
+            eprintln!("Pages can't be switched while the canvas is shared");
+            return;
+        }
+
+        // Park the current page, then take the new one out of the list
+        let page = &mut self.pages[self.current_page];
+        page.elements = mem::take(&mut self.elements);
+        page.view = self.view;
+        page.undo_stack = mem::take(&mut self.undo_stack);
+        page.redo_stack = mem::take(&mut self.redo_stack);
+
+        let page = mem::take(&mut self.pages[index]);
+        self.elements = page.elements;
+        self.view = page.view;
+        self.undo_stack = page.undo_stack;
+        self.redo_stack = page.redo_stack;
+        self.current_page = index;
+
+        self.selection = None;
+        self.invalidate_canvas();
+    }
+
+    // Add a blank page after the current one and go there
+    fn insert_page(&mut self) {
+        if self.sharing.is_some() {
+            eprintln!("Pages can't be added while the canvas is shared");
+            return;
+        }
+        self.pages.insert(self.current_page + 1, Page::default());
+        self.is_dirty = true;
+        self.switch_page(self.current_page + 1);
+    }
+
+    // Push only the tiles painted since the last present, and the snap marker
+    // if it moved, to the screen
+    fn present_dirty(&mut self) {
+        let PhysicalSize { width, height } = self.inner_size;
+        let (origin_x, origin_y) = self.view.origin();
+
+        // Tile rects moved into the window and clipped to it
+        let mut rects: Vec<(u32, u32, u32, u32)> = self
+            .canvas
+            .take_dirty()
+            .into_iter()
+            .filter_map(|(x, y, w, h)| {
+                let (left, top) = ((x - origin_x).max(0), (y - origin_y).max(0));
+                let right = (x - origin_x + w as i32).min(width as i32);
+                let bottom = (y - origin_y + h as i32).min(height as i32);
+                (left < right && top < bottom).then_some((
+                    left as u32,
+                    top as u32,
+                    (right - left) as u32,
+                    (bottom - top) as u32,
+                ))
+            })
+            .collect();
+        let marker = self.snap_target();
+        let tags = self.peer_tags();
+        if rects.is_empty() && marker == self.snap_marker {
+            return;
+        }
+
+        // The page indicator is translucent, so it gets repainted from clean
+        // pixels rather than blended over itself again
+        let label = (self.pages.len() > 1)
+            .then(|| format!("{}/{}", self.current_page + 1, self.pages.len()));
+        if let Some(label) = &label
+            && let Some(rect) = overlay::label_rect(width, height, label)
+        {
+            rects.push(rect);
+        }
+        // The marker is also translucent, and may have moved
+        rects.extend(
+            [self.snap_marker, marker]
+                .into_iter()
+                .flatten()
+                .filter_map(|center| overlay::marker_rect(width, height, center)),
+        );
+        self.snap_marker = marker;
+        // Name tags are translucent too and follow their strokes
+        let tag_rects: Vec<_> = tags
+            .iter()
+            .filter_map(|(point, name)| overlay::tag_rect(width, height, *point, name))
+            .collect();
+        rects.extend(mem::replace(&mut self.tag_rects, tag_rects.clone()));
+        rects.extend(tag_rects);
+
+        let Some(surface) = self.surface.as_mut() else {
+            return;
+        };
+        let Ok(mut buffer) = surface.buffer_mut() else {
+            return;
+        };
+        // The buffer holds the frame presented `age` frames ago. With
+        // double buffering it also misses what the last present changed,
+        // anything older gets a full repaint instead
+        let changed = rects.clone();
+        let has_overlay = self.selection.is_some() || self.floating_image.is_some();
+        let missing = match (buffer.age(), &self.last_damage) {
+            // The selection and the pasted image are only drawn by full repaints
+            _ if has_overlay => None,
+            (1, _) => Some(Vec::new()),
+            (2, Some(last)) => Some(last.clone()),
+            _ => None,
+        };
+        let Some(missing) = missing else {
+            drop(buffer);
+            self.request_repaint();
+            return;
+        };
+        rects.extend(missing);
+        self.last_damage = Some(changed);
+
+        for &rect in &rects {
+            self.background.render(
+                &mut buffer,
+                width,
+                rect,
+                (origin_x, origin_y),
+                self.view.zoom,
+                true,
+            );
+            self.canvas
+                .composite_onto(&mut buffer, width, rect, (origin_x, origin_y));
+        }
+        if let Some(label) = &label {
+            overlay::draw_label(&mut buffer, width, height, label);
+        }
+        if let Some(marker) = marker {
+            overlay::draw_marker(&mut buffer, width, height, marker);
+        }
+        for (point, name) in &tags {
+            overlay::draw_tag(&mut buffer, width, height, *point, name);
+        }
+        let rects: Vec<Rect> = rects
+            .into_iter()
+            .map(|(x, y, w, h)| Rect {
+                x,
+                y,
+                width: NonZeroU32::new(w).unwrap(),
+                height: NonZeroU32::new(h).unwrap(),
+            })
+            .collect();
+        if let Some(frames) = &self.frames {
+            frames.update(width, height, &buffer);
+        }
+        #[cfg(feature = "http")]
+        if let Some(http) = &self.http {
+            http.changed();
+        }
+        let _ = buffer.present_with_damage(&rects);
+    }
+}
+
+impl DrawOnScreen {
+    // Bring the page in line with the shared canvas once the window exists: a
+    // joining instance takes the host's strokes and background, the host
+    // shares the ones it already has
+    fn share_page(&mut self) {
+        let Some(sharing) = &mut self.sharing else {
+            return;
+        };
+        if let Some(page) = self.pending_page.take() {
+            self.elements = page
+                .strokes
+                .into_iter()
+                .map(|stroke| Element::Stroke(stroke.into_stroke()))
+                .collect();
+            if let Some(background) = page.background {
+                background.apply_to(&mut self.background);
+            }
+            self.undo_stack.clear();
+            self.redo_stack.clear();
+            self.invalidate_canvas();
+            return;
+        }
+        sharing.send(&Message::Background(SharedBackground::from_background(
+            &self.background,
+        )));
+        if self
+            .elements
+            .iter()
+            .any(|element| matches!(element, Element::Image { .. }))
+        {
+            eprintln!("Images on this page only show here, they aren't shared");
+        }
+        for element in &mut self.elements {
+            if let Element::Stroke(stroke) = element
+                && stroke.id.is_none()
+            {
+                stroke.id = Some(sharing.next_id());
+                if let Some(stroke) = SharedStroke::from_stroke(stroke) {
+                    sharing.send(&Message::Begin { stroke });
+                }
+            }
+        }
+    }
+
+    fn handle_peer_event(&mut self, event: PeerEvent) {
+        let message = match event {
+            PeerEvent::Message(message) => message,
+            PeerEvent::Disconnected => {
+                eprintln!("Lost the connection to the shared canvas, drawing alone from here");
+                self.sharing = None;
+                self.request_repaint(); // Without the name tags
+                return;
+            }
+        };
+        let Some(sharing) = &mut self.sharing else {
+            return;
+        };
+        match message {
+            Message::Joined(peer) => {
+                println!("{} joined", peer.name);
+                sharing.names.insert(peer.peer, peer.name);
+            }
+            // Their name stays on the strokes they left behind
+            Message::Left { peer } => {
+                if let Some(name) = sharing.names.get(&peer) {
+                    println!("{name} left");
+                }
+            }
+            Message::Begin { stroke } => self.add_shared_stroke(stroke.into_stroke()),
+            Message::Extend { id, points } => {
+                let Some(index) = self.stroke_index(id) else {
+                    return;
+                };
+                let Element::Stroke(stroke) = &mut self.elements[index] else {
+                    return;
+                };
+                let first_new = stroke.points.len();
+                stroke.points.extend(points);
+                self.peer_stroke_changed(index, first_new);
+            }
+            Message::Remove { ids } => {
+                for id in ids {
+                    if let Some(index) = self.stroke_index(id) {
+                        let element = self.elements.remove(index);
+                        self.canvas
+                            .invalidate(raster::bounds(&element, self.view.zoom));
+                    }
+                }
+                self.request_repaint();
+            }
+            Message::Background(background) => {
+                background.apply_to(&mut self.background);
+                self.request_repaint();
+            }
+            Message::Hello { .. } | Message::Welcome(_) => {}
+        }
+        self.is_dirty = true;
+    }
+
+    // Below the stroke being drawn here, which has to stay the last
+    fn add_shared_stroke(&mut self, stroke: Stroke) {
+        let index = if self.is_drawing_stroke() {
+            self.elements.len() - 1
+        } else {
+            self.elements.len()
+        };
+        self.elements.insert(index, Element::Stroke(stroke));
+        self.peer_stroke_changed(index, 0);
+    }
+
+    // A peer's stroke at `index` got points from `first_new` on. On top it is
+    // drawn like a local one, underneath the tiles it touches render again
+    fn peer_stroke_changed(&mut self, index: usize, first_new: usize) {
+        let Element::Stroke(stroke) = &self.elements[index] else {
+            return;
+        };
+        if index + 1 == self.elements.len() {
+            for point in first_new..stroke.points.len() {
+                raster::draw_stroke_point(&mut self.canvas, self.view.zoom, stroke, point);
+            }
+            self.present_dirty();
+        } else {
+            let bounds = raster::bounds(&self.elements[index], self.view.zoom);
+            self.canvas.invalidate(bounds);
+            self.request_repaint();
+        }
+    }
+
+    fn stroke_index(&self, id: StrokeId) -> Option<usize> {
+        self.elements.iter().rposition(
+            |element| matches!(element, Element::Stroke(stroke) if stroke.id == Some(id)),
+        )
+    }
+
+    // Own strokes on a shared canvas, latest last
+    fn own_stroke_indices(&self) -> Vec<usize> {
+        let Some(sharing) = &self.sharing else {
+            return Vec::new();
+        };
+        let is_own = |element: &Element| match element {
+            Element::Stroke(stroke) => stroke.id.is_some_and(|id| id.peer == sharing.peer),
+            _ => false,
+        };
+        (0..self.elements.len())
+            .filter(|&index| is_own(&self.elements[index]))
+            .collect()
+    }
+
+    // Take own strokes off a shared canvas, for everyone
+    fn remove_own_strokes(&mut self, indices: &[usize]) -> Vec<Stroke> {
+        let mut removed = Vec::new();
+        for &index in indices.iter().rev() {
+            let bounds = raster::bounds(&self.elements[index], self.view.zoom);
+            self.canvas.invalidate(bounds);
+            if let Element::Stroke(stroke) = self.elements.remove(index) {
+                removed.push(stroke);
+            }
+        }
+        removed.reverse();
+        if let Some(sharing) = &self.sharing {
+            let ids = removed.iter().filter_map(|stroke| stroke.id).collect();
+            sharing.send(&Message::Remove { ids });
+        }
+        self.is_dirty = true;
+        self.request_repaint();
+        removed
+    }
+
+    fn is_viewing(&self) -> bool {
+        self.sharing
+            .as_ref()
+            .is_some_and(|sharing| sharing.read_only)
+    }
+
+    // A local stroke is being drawn and is the last element
+    fn is_drawing_stroke(&self) -> bool {
+        matches!(self.draw_state, DrawState::Drawing | DrawState::Erasing)
+            && self.last_position.is_some()
+    }
+
+    // Where each other peer last drew, with their name
+    fn peer_tags(&self) -> Vec<((i32, i32), String)> {
+        let Some(sharing) = &self.sharing else {
+            return Vec::new();
+        };
+        let (origin_x, origin_y) = self.view.origin();
+        let mut tagged = Vec::new();
+        let mut tags = Vec::new();
+        for element in self.elements.iter().rev() {
+            if let Element::Stroke(stroke) = element
+                && let Some(id) = stroke.id
+                && id.peer != sharing.peer
+                && !tagged.contains(&id.peer)
+                && let Some(&point) = stroke.points.last()
+            {
+                tagged.push(id.peer);
+                let (x, y) = view::to_canvas(point, self.view.zoom);
+                let name = sharing
+                    .names
+                    .get(&id.peer)
+                    .cloned()
+                    .unwrap_or_else(|| format!("peer {}", id.peer));
+                tags.push(((x - origin_x, y - origin_y), name));
+            }
+        }
+        tags
+    }
+
+    // Everything that reaches the canvas from the mouse and keyboard, live or
+    // replayed
+    fn handle_input(&mut self, event: InputEvent) {
+        if let InputEvent::MouseInput { pressed: true, .. } = event {
+            self.record(self.tool_state());
+        }
+        self.record(event);
+
+        match event {
+            InputEvent::CursorMoved { x, y } => self.cursor_moved((x as i32, y as i32)),
+            InputEvent::MouseInput { button, pressed } => {
+                let state = if pressed {
+                    ElementState::Pressed
+                } else {
+                    ElementState::Released
+                };
+                self.mouse_input(state, button.into());
+            }
+            InputEvent::MouseWheel { y } => self.mouse_wheel(y),
+            InputEvent::Modifiers {
+                control,
+                shift,
+                space,
+            } => {
+                self.is_control_key_pressed = control;
+                self.is_shift_key_pressed = shift;
+                self.is_space_key_pressed = space;
+            }
+            InputEvent::Tool {
+                tool,
+                color,
+                draw_radius,
+                erase_radius,
+            } => {
+                self.set_tool(tool);
+                self.set_color(color);
+                self.draw_brush_radius = draw_radius;
+                self.erase_brush_radius = erase_radius;
+            }
+            InputEvent::Action { action } => self.run_action(action),
+        }
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Clear => self.clear(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::NextPage => self.switch_page(self.current_page + 1),
+            Action::PreviousPage => self.switch_page(self.current_page.saturating_sub(1)),
+            Action::InsertPage => self.insert_page(),
+            Action::ResetView => {
+                // Back to the origin at 100%
+                self.view = View::default();
+                self.invalidate_canvas();
+            }
+            Action::NextBackgroundColor => {
+                let index = background::COLORS
+                    .iter()
+                    .position(|&color| color == self.background.color)
+                    .map_or(0, |index| (index + 1) % background::COLORS.len());
+                self.background.color = background::COLORS[index];
+                self.background_changed();
+            }
+            Action::NextBackgroundPattern => {
+                self.background.pattern = self.background.pattern.next();
+                self.background_changed();
+            }
+            Action::WiderBackground | Action::NarrowerBackground => {
+                let factor = if action == Action::WiderBackground {
+                    1.25
+                } else {
+                    1.0 / 1.25
+                };
+                self.background.spacing = (self.background.spacing * factor)
+                    .clamp(background::MIN_SPACING, background::MAX_SPACING);
+                self.background_changed();
+            }
+            Action::ToggleSnapping => {
+                // Only has an effect on grid and dot backgrounds
+                self.is_snapping = !self.is_snapping;
+                self.request_repaint();
+            }
+            Action::PlaceImage => self.commit_floating_image(),
+            Action::Cancel => {
+                if self.floating_image.is_some() {
+                    // Drop the pasted image without committing it
+                    self.floating_image = None;
+                    self.draw_state = DrawState::Idle;
+                    self.request_repaint();
+                } else if self.selection.is_some() {
+                    self.selection = None;
+                    self.request_repaint();
+                }
+            }
+        }
+    }
+
+    // The canvas action a key press stands for, if any
+    fn key_action(&self, key: KeyCode) -> Option<Action> {
+        let control = self.is_control_key_pressed;
+        Some(match key {
+            KeyCode::Backspace => Action::Clear,
+            KeyCode::KeyZ if control => Action::Undo,
+            KeyCode::KeyR if control => Action::Redo,
+            KeyCode::PageDown => Action::NextPage,
+            KeyCode::PageUp => Action::PreviousPage,
+            KeyCode::Insert => Action::InsertPage,
+            KeyCode::Home => Action::ResetView,
+            KeyCode::KeyB if self.is_shift_key_pressed => Action::NextBackgroundColor,
+            KeyCode::KeyB => Action::NextBackgroundPattern,
+            KeyCode::BracketRight => Action::WiderBackground,
+            KeyCode::BracketLeft => Action::NarrowerBackground,
+            KeyCode::KeyG => Action::ToggleSnapping,
+            KeyCode::Enter | KeyCode::NumpadEnter => Action::PlaceImage,
+            KeyCode::Escape if self.floating_image.is_some() || self.selection.is_some() => {
+                Action::Cancel
+            }
+            _ => return None,
+        })
+    }
+
+    fn tool_state(&self) -> InputEvent {
+        InputEvent::Tool {
+            tool: self.tool,
+            color: self.pointer_color,
+            draw_radius: self.draw_brush_radius,
+            erase_radius: self.erase_brush_radius,
+        }
+    }
+
+    fn record(&mut self, event: InputEvent) {
+        if let Some(recorder) = &mut self.recorder
+            && let Err(err) = recorder.record(event)
+        {
+            eprintln!("Recording stopped: {err}");
+            self.recorder = None;
+        }
+    }
+
+    // Play whatever is due, then report when the next event is
+    fn advance_replay(&mut self) -> Option<Instant> {
+        while let Some(event) = self
+            .replay
+            .as_mut()
+            .and_then(|replay| replay.next_due(Instant::now()))
+        {
+            self.handle_input(event);
+        }
+        let replay = self.replay.as_ref()?;
+        if replay.is_finished() {
+            println!("Replay finished");
+            self.replay = None;
+            return None;
+        }
+        replay.deadline()
+    }
+
+    fn cursor_moved(&mut self, position: (i32, i32)) {
+        let previous_position = self.position;
+        self.position = Some(position);
+
+        if self.draw_state == DrawState::Moving {
+            if let (Some(floating_image), Some(previous), Some(current)) = (
+                self.floating_image.as_mut(),
+                previous_position,
+                self.position,
+            ) {
+                floating_image.move_by(current.0 - previous.0, current.1 - previous.1);
+                self.request_repaint();
+            }
+            return;
+        }
 
-        let mut min_x = i32::MAX;
-        let mut max_x = i32::MIN;
-        let mut min_y = i32::MAX;
-        let mut max_y = i32::MIN;
+        if self.draw_state == DrawState::Panning {
+            if let (Some(previous), Some(current)) = (previous_position, self.position) {
+                // Tiles are kept while panning, only newly uncovered
+                // ones get rendered
+                self.view
+                    .pan(current.0 - previous.0, current.1 - previous.1);
+                self.request_repaint();
+            }
+            return;
+        }
 
-        let radius_f = radius as f32;
-        let color_u32 = color as u32;
+        if self.draw_state == DrawState::Selecting {
+            if let (Some(selection), Some(current)) = (self.selection.as_mut(), self.position) {
+                selection.end = current;
+                self.request_repaint();
+            }
+            return;
+        }
 
-        // Early bounds check with antialiasing margin
-        let aa_radius = radius + 1;
-        if cx + aa_radius < 0
-            || cx - aa_radius >= width
-            || cy + aa_radius < 0
-            || cy - aa_radius >= height
-        {
+        // This block remains mostly the same, handling continuous drawing
+        if self.draw_state == DrawState::Idle {
+            self.last_position = None;
+            self.present_dirty(); // Moves the snap marker along
             return;
         }
 
-        // Optimize bounds for the loop with antialiasing
-        let start_y = (-aa_radius).max(-cy);
-        let end_y = aa_radius.min(height - cy - 1);
-        let start_x = (-aa_radius).max(-cx);
-        let end_x = aa_radius.min(width - cx - 1);
+        let current_pos = position;
+        let color = if self.draw_state == DrawState::Erasing {
+            Color::Black as u32
+        } else {
+            self.pointer_color
+        };
 
-        for dy in start_y..=end_y {
-            for dx in start_x..=end_x {
-                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+        if self.last_position.is_none() {
+            // save state at the start of a new stroke
+            self.begin_stroke(current_pos, color, self.draw_state == DrawState::Erasing);
+        } else if !self.extend_stroke(current_pos) {
+            return;
+        }
+        self.draw_stroke_tip();
 
-                if distance <= radius_f + 1.0 {
-                    let x = cx + dx;
-                    let y = cy + dy;
+        self.last_position = Some(current_pos);
+    }
 
-                    // Ensure x and y are within bounds before calculating index
-                    if x >= 0 && x < width && y >= 0 && y < height {
-                        let idx = (y * width + x) as usize;
+    // `y` is in lines or pixels, only its sign matters
+    fn mouse_wheel(&mut self, y: f32) {
+        if let Some(floating_image) = self.floating_image.as_mut() {
+            if y > 0.0 {
+                floating_image.scale_by(1.1);
+            } else if y < 0.0 {
+                floating_image.scale_by(1.0 / 1.1);
+            }
+            self.request_repaint();
+        } else if self.is_control_key_pressed {
+            // Zoom around the cursor so the point under it stays put
+            let anchor = self.position.unwrap_or((
+                self.inner_size.width as i32 / 2,
+                self.inner_size.height as i32 / 2,
+            ));
+            if y > 0.0 {
+                self.view.zoom_at(anchor, 1.1);
+            } else if y < 0.0 {
+                self.view.zoom_at(anchor, 1.0 / 1.1);
+            }
+            if self.view.zoom != self.canvas.zoom() {
+                self.invalidate_canvas();
+            }
+        } else if y > 0.0 {
+            if self.draw_state == DrawState::Erasing {
+                self.erase_brush_radius = (self.erase_brush_radius + 1).min(50);
+            } else {
+                self.draw_brush_radius = (self.draw_brush_radius + 1).min(20);
+            }
+        } else if y < 0.0 {
+            if self.draw_state == DrawState::Erasing {
+                self.erase_brush_radius = (self.erase_brush_radius - 1).max(1);
+            } else {
+                self.draw_brush_radius = (self.draw_brush_radius - 1).max(1);
+            }
+        }
+    }
 
-                        let alpha = if distance <= radius_f {
-                            1.0 // Full opacity inside the circle
+    fn mouse_input(&mut self, state: ElementState, button: MouseButton) {
+        match (state, button) {
+            _ if self.floating_image.is_some() => {
+                // While an image is pasted the left button drags it instead of drawing
+                if state == ElementState::Pressed && button == MouseButton::Left {
+                    self.draw_state = DrawState::Moving;
+                } else if state == ElementState::Released {
+                    self.draw_state = DrawState::Idle;
+                }
+            }
+            (ElementState::Pressed, MouseButton::Left) if self.is_shift_key_pressed => {
+                if let Some(position) = self.position {
+                    self.selection = Some(Selection::new(position));
+                    self.draw_state = DrawState::Selecting;
+                }
+            }
+            (ElementState::Pressed, button)
+                if button == MouseButton::Middle
+                    || (button == MouseButton::Left && self.is_space_key_pressed) =>
+            {
+                self.draw_state = DrawState::Panning;
+            }
+            // Viewers of a broadcast can look around and select, not draw
+            (ElementState::Pressed, _) if self.is_viewing() => {}
+            (ElementState::Pressed, button) => {
+                if self.window.is_some() {
+                    // Get the current cursor position when the mouse button is pressed
+                    // The left button uses the current tool, the right one always erases
+                    let is_eraser = button == MouseButton::Right
+                        || (button == MouseButton::Left && self.tool == Tool::Eraser);
+                    if let Some(current_pos) = self.position {
+                        let color = if is_eraser {
+                            Color::Black as u32
                         } else {
-                            // Antialiasing: fade out at the edge
-                            1.0 - (distance - radius_f)
+                            self.pointer_color
                         };
 
-                        if alpha > 0.0 {
-                            let current_color = self.pixels[idx]; // Read from self.pixels
-
-                            let r = (((color_u32 >> 16) & 0xFF) as f32 * alpha
-                                + ((current_color >> 16) & 0xFF) as f32 * (1.0 - alpha))
-                                as u32;
-                            let g = (((color_u32 >> 8) & 0xFF) as f32 * alpha
-                                + ((current_color >> 8) & 0xFF) as f32 * (1.0 - alpha))
-                                as u32;
-                            let b = ((color_u32 & 0xFF) as f32 * alpha
-                                + (current_color & 0xFF) as f32 * (1.0 - alpha))
-                                as u32;
-
-                            self.pixels[idx] = 0xFF000000 | (r << 16) | (g << 8) | b; // Write to self.pixels
-
-                            min_x = min_x.min(x);
-                            max_x = max_x.max(x);
-                            min_y = min_y.min(y);
-                            max_y = max_y.max(y);
+                        // Save state before drawing the initial dot
+                        self.begin_stroke(current_pos, color, is_eraser);
+
+                        // Draw a circle at the clicked position immediately
+                        self.draw_stroke_tip();
+
+                        self.last_position = Some(current_pos);
+                    }
+
+                    match button {
+                        MouseButton::Left | MouseButton::Right if is_eraser => {
+                            self.draw_state = DrawState::Erasing;
+                        }
+                        MouseButton::Left => {
+                            self.draw_state = DrawState::Drawing;
                         }
+                        _ => {}
                     }
                 }
             }
+            (ElementState::Released, _) => {
+                self.draw_state = DrawState::Idle;
+                self.last_position = None;
+            }
         }
+    }
 
-        if min_x <= max_x && min_y <= max_y {
-            let rect = Rect {
-                x: min_x as u32,
-                y: min_y as u32,
-                width: NonZeroU32::new((max_x - min_x + 1) as u32).unwrap(),
-                height: NonZeroU32::new((max_y - min_y + 1) as u32).unwrap(),
-            };
-
-            // Only present the damaged region
-            if let Some(surface) = self.surface.as_mut() {
-                if let Ok(mut buffer) = surface.buffer_mut() {
-                    let width = self.inner_size.width as usize;
-                    // Copy only the affected region from self.pixels to the buffer
-                    for y in rect.y..(rect.y + rect.height.get()) {
-                        let src_start = (y * width as u32 + rect.x) as usize;
-                        let src_end = (y * width as u32 + rect.x + rect.width.get()) as usize;
-                        let dest_start = (y * width as u32 + rect.x) as usize;
-                        buffer[dest_start..src_end]
-                            .copy_from_slice(&self.pixels[src_start..src_end]);
-                    }
-                    let _ = buffer.present_with_damage(&[rect]);
+    // Settings from the config file, then the command line on top. A loaded
+    // session brings back its own colors and brushes, but an explicit
+    // `--background` still applies to it
+    fn configure(
+        &mut self,
+        mut options: cli::Options,
+        proxy: EventLoopProxy<UserEvent>,
+    ) -> Result<(), String> {
+        let frames = options.frames.take();
+        let (frames_raw, frames_fps) = (options.frames_raw, options.frames_fps);
+        self.apply_options(options)?;
+
+        if let Some(target) = frames {
+            let target = if target.as_os_str() == "-" {
+                if frames_raw {
+                    FrameTarget::Raw
+                } else {
+                    FrameTarget::Y4m
                 }
-            }
+            } else {
+                FrameTarget::Png(target)
+            };
+            let frames = FrameDump::start(target, frames_fps.unwrap_or(30), proxy)
+                .map_err(|err| format!("Failed to start writing frames: {err}"))?;
+            self.frames = Some(frames);
         }
+        Ok(())
     }
 
-    // Draw interpolated line between two points using Bresenham algorithm with antialiasing
-    fn draw_interpolated_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
-        let width = self.inner_size.width as i32;
-        let height = self.inner_size.height as i32;
-
-        let mut points = Vec::with_capacity(256); // Pre-allocate reasonable capacity
-
-        points.clear();
-
-        let dx = (x1 - x0).abs();
-        let dy = -(y1 - y0).abs();
-
-        let sx = if x0 < x1 { 1 } else { -1 };
-        let sy = if y0 < y1 { 1 } else { -1 };
+    // The config file with the command line on top, everything but the frame
+    // dump, which needs the event loop
+    fn apply_options(&mut self, options: cli::Options) -> Result<(), String> {
+        let config_path = options
+            .config
+            .clone()
+            .or_else(|| config::default_path().filter(|path| path.exists()));
+        let config = match &config_path {
+            Some(path) => Config::load(path)
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))?,
+            None => Config::default(),
+        };
 
-        let mut err = dx + dy;
-        let mut x = x0;
-        let mut y = y0;
+        if let Some(palette) = config.palette {
+            self.palette = palette;
+        }
+        if let Some(color) = config.pointer_color {
+            self.pointer_color = color;
+        }
+        if let Some(radius) = config.draw_brush_radius {
+            self.draw_brush_radius = radius;
+        }
+        if let Some(radius) = config.erase_brush_radius {
+            self.erase_brush_radius = radius;
+        }
+        if let Some(spacing) = config.spacing {
+            self.background.spacing = spacing;
+        }
+        if let Some(spec) = config.background {
+            self.apply_background_spec(spec)?;
+        }
+        self.output_dir = options.output_dir.or(config.output_dir);
+        self.fullscreen = options.fullscreen || config.fullscreen;
+        self.monitor = options.monitor.or(config.monitor);
 
-        loop {
-            points.push((x, y));
+        if let Some(directory) = &self.output_dir
+            && let Err(err) = std::fs::create_dir_all(directory)
+        {
+            return Err(format!("Failed to create {}: {err}", directory.display()));
+        }
 
-            if x == x1 && y == y1 {
-                break;
+        if let Some(path) = options.load {
+            if is_png(&path) {
+                self.pending_image = Some(open_png(&path)?);
+            } else {
+                let session = Session::load(&path)
+                    .map_err(|err| format!("Failed to load {}: {err}", path.display()))?;
+                self.pending_session = Some(session);
+                self.session_path = Some(path);
             }
+        }
+        if let Some(spec) = options.background {
+            self.apply_background_spec(spec)?;
+        }
 
-            let e2 = 2 * err;
-            if e2 >= dy {
-                err += dy;
-                x += sx;
+        match options.freeze {
+            Some(cli::Freeze::Capture) => {
+                let screenshot = capture::capture()
+                    .map_err(|err| format!("Failed to capture the screen: {err}"))?;
+                self.pending_screenshot = Some(screenshot);
             }
-            if e2 <= dx {
-                err += dx;
-                y += sy;
+            Some(cli::Freeze::File(path)) => {
+                let screenshot = capture::load(&path)
+                    .map_err(|err| format!("Failed to open {}: {err}", path.display()))?;
+                self.pending_screenshot = Some(screenshot);
             }
+            None => {}
         }
 
-        let mut min_x = i32::MAX;
-        let mut max_x = i32::MIN;
-        let mut min_y = i32::MAX;
-        let mut max_y = i32::MIN;
-
-        let radius = if self.draw_state == DrawState::Erasing {
-            self.erase_brush_radius
-        } else {
-            self.draw_brush_radius
-        };
-
-        let radius_f = radius as f32;
-        let color_u32 = color as u32;
-        let aa_radius = radius + 1;
-
-        // Draw antialiased circles at each point along the line
-        for &(px, py) in &points {
-            // Bounds check for the circle center with antialiasing margin
-            if px + aa_radius < 0
-                || px - aa_radius >= width
-                || py + aa_radius < 0
-                || py - aa_radius >= height
-            {
-                continue;
-            }
-
-            // Optimize bounds for the circle with antialiasing
-            let start_dy = (-aa_radius).max(-py);
-            let end_dy = aa_radius.min(height - py - 1);
-            let start_dx = (-aa_radius).max(-px);
-            let end_dx = aa_radius.min(width - px - 1);
-
-            for dy in start_dy..=end_dy {
-                for dx in start_dx..=end_dx {
-                    let distance = ((dx * dx + dy * dy) as f32).sqrt();
-
-                    if distance <= radius_f + 1.0 {
-                        let x = px + dx;
-                        let y = py + dy;
-
-                        // Ensure x and y are within bounds before calculating index
-                        if x >= 0 && x < width && y >= 0 && y < height {
-                            let idx = (y * width + x) as usize;
-
-                            let alpha = if distance <= radius_f {
-                                1.0 // Full opacity inside the circle
-                            } else {
-                                // Antialiasing: fade out at the edge
-                                1.0 - (distance - radius_f)
-                            };
-
-                            if alpha > 0.0 {
-                                let current_color = self.pixels[idx]; // Read from self.pixels
-
-                                let r = (((color_u32 >> 16) & 0xFF) as f32 * alpha
-                                    + ((current_color >> 16) & 0xFF) as f32 * (1.0 - alpha))
-                                    as u32;
-                                let g = (((color_u32 >> 8) & 0xFF) as f32 * alpha
-                                    + ((current_color >> 8) & 0xFF) as f32 * (1.0 - alpha))
-                                    as u32;
-                                let b = ((color_u32 & 0xFF) as f32 * alpha
-                                    + (current_color & 0xFF) as f32 * (1.0 - alpha))
-                                    as u32;
-
-                                self.pixels[idx] = 0xFF000000 | (r << 16) | (g << 8) | b; // Write to self.pixels
-
-                                min_x = min_x.min(x);
-                                max_x = max_x.max(x);
-                                min_y = min_y.min(y);
-                                max_y = max_y.max(y);
-                            }
-                        }
-                    }
-                }
-            }
+        if let Some(path) = options.record {
+            let recorder = Recorder::create(&path)
+                .map_err(|err| format!("Failed to create {}: {err}", path.display()))?;
+            self.recorder = Some(recorder);
+        }
+        if let Some(path) = options.replay {
+            let replay = Replay::load(&path, options.replay_speed.unwrap_or(1.0))
+                .map_err(|err| format!("Failed to load {}: {err}", path.display()))?;
+            self.replay = Some(replay);
         }
 
-        if min_x <= max_x && min_y <= max_y {
-            let rect = Rect {
-                x: min_x as u32,
-                y: min_y as u32,
-                width: NonZeroU32::new((max_x - min_x + 1) as u32).unwrap(),
-                height: NonZeroU32::new((max_y - min_y + 1) as u32).unwrap(),
-            };
+        Ok(())
+    }
 
-            // Only present the damaged region
-            if let Some(surface) = self.surface.as_mut() {
-                if let Ok(mut buffer) = surface.buffer_mut() {
-                    let width = self.inner_size.width as usize;
-                    // Copy only the affected region from self.pixels to the buffer
-                    for y in rect.y..(rect.y + rect.height.get()) {
-                        let src_start = (y * width as u32 + rect.x) as usize;
-                        let src_end = (y * width as u32 + rect.x + rect.width.get()) as usize;
-                        let dest_start = (y * width as u32 + rect.x) as usize;
-                        buffer[dest_start..src_end]
-                            .copy_from_slice(&self.pixels[src_start..src_end]);
-                    }
-                    let _ = buffer.present_with_damage(&[rect]);
-                }
-            }
+    // To the pending session when there is one, so it isn't overwritten later
+    fn apply_background_spec(&mut self, spec: BackgroundSpec) -> Result<(), String> {
+        let background = match &mut self.pending_session {
+            Some(session) => &mut session.background,
+            None => &mut self.background,
+        };
+        match spec {
+            BackgroundSpec::Pattern(pattern) => background.pattern = pattern,
+            BackgroundSpec::Color(color) => background.color = color,
+            BackgroundSpec::Image(path) => self.pending_image = Some(open_png(&path)?),
         }
+        Ok(())
     }
 }
 
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+fn open_png(path: &Path) -> Result<Image, String> {
+    std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| Image::decode_png(&bytes).map_err(|err| err.to_string()))
+        .map_err(|err| format!("Failed to open {}: {err}", path.display()))
+}
+
 impl Default for DrawOnScreen {
     fn default() -> Self {
         Self {
@@ -353,16 +1569,59 @@ impl Default for DrawOnScreen {
 
             inner_size: PhysicalSize::new(0, 0),
 
-            pixels: vec![], // Initialize as empty, will be sized on resume
+            canvas: TiledCanvas::new(1.0),
+            background: Background::default(),
+            elements: Vec::new(),
+            view: View::default(),
+            pages: vec![Page::default()],
+            current_page: 0,
             position: None,
             last_position: None,
 
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
 
-            is_control_key_pressed: false,
+            session_path: None,
+            output_dir: None,
+            fullscreen: false,
+            monitor: None,
+            pending_session: None,
+            is_hidden: false,
+            control_socket: None,
+            dbus: None,
+            recorder: None,
+            replay: None,
+            frames: None,
+            #[cfg(feature = "http")]
+            http: None,
+            sharing: None,
+            pending_page: None,
+            tag_rects: Vec::new(),
+            needs_repaint: true,
+            last_damage: None,
+            pending_screenshot: None,
+            pending_image: None,
+            is_dirty: false,
+            next_autosave: Instant::now() + AUTOSAVE_INTERVAL,
 
-            pointer_color: Color::White,
+            is_control_key_pressed: false,
+            is_shift_key_pressed: false,
+            is_space_key_pressed: false,
+            is_snapping: false,
+            snap_marker: None,
+
+            floating_image: None,
+            selection: None,
+            clipboard: None,
+
+            pointer_color: Color::White as u32,
+            tool: Tool::Pen,
+            palette: vec![
+                Color::Red as u32,
+                Color::Green as u32,
+                Color::Blue as u32,
+                Color::White as u32,
+            ],
             draw_state: DrawState::Idle,
             draw_brush_radius: 1, // Default brush size
             erase_brush_radius: 3,
@@ -370,14 +1629,52 @@ impl Default for DrawOnScreen {
     }
 }
 
-impl ApplicationHandler for DrawOnScreen {
+impl ApplicationHandler<UserEvent> for DrawOnScreen {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         println!("Resumed");
 
-        let window_attributes = Window::default_attributes()
+        let mut window_attributes = Window::default_attributes()
             .with_title("Draw On Screen - Optimized")
             .with_cursor(CursorIcon::Pointer);
 
+        if let Some(session) = &self.pending_session {
+            window_attributes =
+                window_attributes.with_inner_size(PhysicalSize::new(session.width, session.height));
+        }
+
+        // An opened image gets a window its size, scaled down to fit the screen
+        // if need be
+        let mut image_zoom = 1.0;
+        if let Some(image) = &self.pending_image {
+            if let Some(monitor) = event_loop.primary_monitor() {
+                let PhysicalSize { width, height } = monitor.size();
+                image_zoom = (IMAGE_FIT * width as f32 / image.width as f32)
+                    .min(IMAGE_FIT * height as f32 / image.height as f32)
+                    .min(1.0);
+            }
+            window_attributes = window_attributes.with_inner_size(PhysicalSize::new(
+                ((image.width as f32 * image_zoom).round() as u32).max(1),
+                ((image.height as f32 * image_zoom).round() as u32).max(1),
+            ));
+        }
+
+        let monitor = self.monitor.and_then(|index| {
+            let monitor = event_loop.available_monitors().nth(index);
+            if monitor.is_none() {
+                eprintln!("There is no monitor {index}, opening on the current one");
+            }
+            monitor
+        });
+        if let Some(monitor) = &monitor {
+            window_attributes = window_attributes.with_position(monitor.position());
+        }
+
+        // The frozen screenshot has to line up with the screen it was taken of
+        if self.fullscreen || self.pending_screenshot.is_some() {
+            window_attributes =
+                window_attributes.with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        }
+
         let window = event_loop.create_window(window_attributes).unwrap();
 
         let window = Rc::new(window);
@@ -391,92 +1688,214 @@ impl ApplicationHandler for DrawOnScreen {
         self.surface = Some(surface);
         self.inner_size = window.inner_size();
 
-        // Initialize pixels vector with the correct size and black color
-        self.pixels =
-            vec![Color::Black as u32; (self.inner_size.width * self.inner_size.height) as usize];
+        if let Some(session) = self.pending_session.take() {
+            self.apply_session(session);
+        }
+
+        if let Some(screenshot) = self.pending_screenshot.take() {
+            let (position, size) = window
+                .current_monitor()
+                .map(|monitor| {
+                    let position = monitor.position();
+                    let size = monitor.size();
+                    ((position.x, position.y), (size.width, size.height))
+                })
+                .unwrap_or(((0, 0), (self.inner_size.width, self.inner_size.height)));
+            self.background.image = Some(Arc::new(screenshot.for_monitor(position, size)));
+        }
+
+        if let Some(image) = self.pending_image.take() {
+            self.background.image = Some(Arc::new(image));
+            self.view = View {
+                zoom: image_zoom,
+                ..View::default()
+            };
+        }
+
+        self.share_page();
+
+        // A replay starts from the view it was recorded in, and in a window of
+        // the same size if the window manager allows
+        if let Some(replay) = &mut self.replay {
+            let PhysicalSize { width, height } = self.inner_size;
+            if (width, height) != (replay.header.width, replay.header.height) {
+                let _ = window.request_inner_size(PhysicalSize::new(
+                    replay.header.width,
+                    replay.header.height,
+                ));
+            }
+            self.view = replay.header.view();
+            replay.start();
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            let PhysicalSize { width, height } = self.inner_size;
+            if let Err(err) = recorder.start(&Header::new(width, height, self.view)) {
+                eprintln!("Recording stopped: {err}");
+                self.recorder = None;
+            }
+        }
+
+        self.invalidate_canvas();
+    }
+
+    // Autosave periodically while the event loop is otherwise idle, and keep a
+    // replay going
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if Instant::now() >= self.next_autosave {
+            self.autosave();
+            self.next_autosave = Instant::now() + AUTOSAVE_INTERVAL;
+        }
+        let wake_up = match self.advance_replay() {
+            Some(deadline) => deadline.min(self.next_autosave),
+            None => self.next_autosave,
+        };
+        event_loop.set_control_flow(ControlFlow::WaitUntil(wake_up));
+    }
 
-        window.request_redraw();
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: UserEvent) {
+        match event {
+            UserEvent::Control(event) => {
+                let reply = self.handle_request(event_loop, event.request);
+                let _ = event.reply.send(reply); // The client may have hung up already
+            }
+            UserEvent::Peer(event) => self.handle_peer_event(event),
+            UserEvent::FrameWanted => {
+                // Presenting the frame as it is hands it over, see `RedrawRequested`
+                if self.frames.as_ref().is_some_and(FrameDump::is_wanted)
+                    && let Some(window) = &self.window
+                {
+                    window.request_redraw();
+                }
+            }
+            #[cfg(feature = "http")]
+            UserEvent::Canvas(reply) => {
+                let (width, height, rgba) = self.window_rgba();
+                let _ = reply.send(CanvasImage {
+                    width,
+                    height,
+                    rgba,
+                });
+            }
+        }
     }
 
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        window_id: WindowId,
+        _window_id: WindowId,
         event: WindowEvent,
     ) {
         match event {
-            WindowEvent::ModifiersChanged(modifiers) => {
-                self.is_control_key_pressed = modifiers.state().control_key()
+            // Live keys would get in the way of a replay, like the mouse below
+            WindowEvent::ModifiersChanged(modifiers) if self.replay.is_none() => {
+                self.handle_input(InputEvent::Modifiers {
+                    control: modifiers.state().control_key(),
+                    shift: modifiers.state().shift_key(),
+                    space: self.is_space_key_pressed,
+                });
             }
             WindowEvent::CloseRequested => {
                 println!("Window closed");
-                event_loop.exit();
+                self.quit(event_loop);
             }
-            WindowEvent::KeyboardInput { event, .. } => {
-                if event.state == ElementState::Pressed && !event.repeat {
-                    use winit::keyboard::ModifiersState;
-
-                    match event.physical_key {
-                        PhysicalKey::Code(KeyCode::Digit1) => {
-                            self.pointer_color = Color::Red;
-                        }
-                        PhysicalKey::Code(KeyCode::Digit2) => {
-                            self.pointer_color = Color::Green;
-                        }
-                        PhysicalKey::Code(KeyCode::Digit3) => {
-                            self.pointer_color = Color::Blue;
-                        }
-                        PhysicalKey::Code(KeyCode::Digit0) => {
-                            self.pointer_color = Color::White;
+            // Held down to pan with the left button, so releases matter too
+            WindowEvent::KeyboardInput { event, .. }
+                if event.physical_key == PhysicalKey::Code(KeyCode::Space) =>
+            {
+                if self.replay.is_some() {
+                    return;
+                }
+                let is_pressed = event.state == ElementState::Pressed;
+                if is_pressed != self.is_space_key_pressed {
+                    self.handle_input(InputEvent::Modifiers {
+                        control: self.is_control_key_pressed,
+                        shift: self.is_shift_key_pressed,
+                        space: is_pressed,
+                    });
+                }
+            }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed && !event.repeat =>
+            {
+                // Canvas actions are recorded, and left to the replay while
+                // one runs
+                if let PhysicalKey::Code(key) = event.physical_key
+                    && let Some(action) = self.key_action(key)
+                {
+                    if self.replay.is_none() {
+                        self.handle_input(InputEvent::Action { action });
+                    }
+                    return;
+                }
+                match event.physical_key {
+                    PhysicalKey::Code(
+                        key @ (KeyCode::Digit1
+                        | KeyCode::Digit2
+                        | KeyCode::Digit3
+                        | KeyCode::Digit0),
+                    ) => {
+                        let slot = match key {
+                            KeyCode::Digit1 => 0,
+                            KeyCode::Digit2 => 1,
+                            KeyCode::Digit3 => 2,
+                            _ => 3,
+                        };
+                        if let Some(&color) = self.palette.get(slot) {
+                            self.set_color(color);
                         }
-                        PhysicalKey::Code(KeyCode::Equal)
-                        | PhysicalKey::Code(KeyCode::NumpadAdd) => {
-                            if self.draw_state == DrawState::Erasing {
-                                self.erase_brush_radius = (self.erase_brush_radius + 1).min(50); // Max erasing size
-                            } else {
-                                self.draw_brush_radius = (self.draw_brush_radius + 1).min(20); // Max drawing size
-                            }
+                    }
+                    PhysicalKey::Code(KeyCode::Equal) | PhysicalKey::Code(KeyCode::NumpadAdd) => {
+                        if self.draw_state == DrawState::Erasing {
+                            self.erase_brush_radius = (self.erase_brush_radius + 1).min(50); // Max erasing size
+                        } else {
+                            self.draw_brush_radius = (self.draw_brush_radius + 1).min(20); // Max drawing size
                         }
-                        PhysicalKey::Code(KeyCode::Minus)
-                        | PhysicalKey::Code(KeyCode::NumpadSubtract) => {
-                            if self.draw_state == DrawState::Erasing {
-                                self.erase_brush_radius = (self.erase_brush_radius - 1).max(1);
-                            } else {
-                                self.draw_brush_radius = (self.draw_brush_radius - 1).max(1);
-                            }
+                    }
+                    PhysicalKey::Code(KeyCode::Minus)
+                    | PhysicalKey::Code(KeyCode::NumpadSubtract) => {
+                        if self.draw_state == DrawState::Erasing {
+                            self.erase_brush_radius = (self.erase_brush_radius - 1).max(1);
+                        } else {
+                            self.draw_brush_radius = (self.draw_brush_radius - 1).max(1);
                         }
+                    }
 
-                        PhysicalKey::Code(KeyCode::Backspace) => {
-                            // Clear screen
-                            self.save_state();
-                            self.pixels
-                                .iter_mut()
-                                .for_each(|pixel| *pixel = Color::Black as u32); // Clear self.pixels
-                            if let Some(window) = &self.window {
-                                window.request_redraw(); // Request redraw to show cleared screen
-                            }
-                        }
+                    PhysicalKey::Code(KeyCode::KeyE) if self.is_control_key_pressed => {
+                        self.export_svg();
+                    }
 
-                        PhysicalKey::Code(KeyCode::KeyZ) => {
-                            if self.is_control_key_pressed {
-                                if let Some(last) = self.undo_stack.pop() {
-                                    self.redo_stack.push(self.pixels.clone()); // Push current state to redo
-                                    self.restore_state(last);
-                                }
-                            }
-                        }
+                    PhysicalKey::Code(KeyCode::KeyE) => {
+                        self.set_tool(if self.tool == Tool::Eraser {
+                            Tool::Pen
+                        } else {
+                            Tool::Eraser
+                        });
+                    }
 
-                        PhysicalKey::Code(KeyCode::KeyR) => {
-                            if self.is_control_key_pressed {
-                                if let Some(next) = self.redo_stack.pop() {
-                                    self.undo_stack.push(self.pixels.clone()); // Push current state to undo
-                                    self.restore_state(next);
-                                }
-                            }
-                        }
+                    PhysicalKey::Code(KeyCode::KeyP) if self.is_control_key_pressed => {
+                        self.export_pdf();
+                    }
 
-                        _ => {}
+                    PhysicalKey::Code(KeyCode::KeyC) if self.is_control_key_pressed => {
+                        self.copy_to_clipboard();
+                    }
+
+                    PhysicalKey::Code(KeyCode::KeyS)
+                        if self.is_control_key_pressed && self.is_shift_key_pressed =>
+                    {
+                        self.save_session();
+                    }
+
+                    PhysicalKey::Code(KeyCode::KeyS) if self.is_control_key_pressed => {
+                        let _ = self.save_to_file(); // Already reported
+                    }
+
+                    PhysicalKey::Code(KeyCode::KeyV) if self.is_control_key_pressed => {
+                        self.paste_from_clipboard();
                     }
+
+                    _ => {}
                 }
             }
             WindowEvent::Resized(size) => {
@@ -490,203 +1909,106 @@ impl ApplicationHandler for DrawOnScreen {
                         )
                         .unwrap();
 
-                    let old_width = self.inner_size.width;
-                    let old_height = self.inner_size.height;
-
-                    let new_width = width;
-                    let new_height = height;
-
-                    // Create a new pixels buffer for the new size, initialized to black
-                    let mut new_pixels =
-                        vec![Color::Black as u32; (new_width * new_height) as usize];
-
-                    let copy_width = old_width.min(new_width);
-                    let copy_height = old_height.min(new_height);
-
-                    // Copy existing pixels to the new buffer
-                    for y in 0..copy_height {
-                        for x in 0..copy_width {
-                            let old_idx = (y * old_width + x) as usize;
-                            let new_idx = (y * new_width + x) as usize;
-
-                            // Ensure indices are within bounds of old and new pixel buffers
-                            if old_idx < self.pixels.len() && new_idx < new_pixels.len() {
-                                new_pixels[new_idx] = self.pixels[old_idx];
-                            }
-                        }
-                    }
-
-                    // Update self.pixels with the new, resized content
-                    self.pixels = new_pixels;
+                    // The document doesn't depend on the window, so just show
+                    // more or less of it
                     self.inner_size = size;
-
-                    // --- FIX: Clear undo/redo stacks on resize to prevent size mismatches ---
-                    self.undo_stack.clear();
-                    self.redo_stack.clear();
-                    // ---------------------------------------------------------------------
-
-                    // Request a redraw to push the new self.pixels to softbuffer
-                    if let Some(window) = &self.window {
-                        window.request_redraw();
-                    }
+                    self.request_repaint();
                 }
             }
-            WindowEvent::CursorMoved { position, .. } => {
-                self.position = Some((position.x as i32, position.y as i32));
-                // This block remains mostly the same, handling continuous drawing
-                if self.draw_state == DrawState::Idle {
-                    self.last_position = None;
-                    return;
-                }
-
-                if self.last_position.is_none() {
-                    self.save_state(); // save state at the start of a new stroke
-                }
-
-                let current_pos = (position.x as i32, position.y as i32);
-                let color = if self.draw_state == DrawState::Erasing {
-                    Color::Black
-                } else {
-                    self.pointer_color
-                };
-
-                let current_brush_radius = if self.draw_state == DrawState::Erasing {
-                    self.erase_brush_radius
-                } else {
-                    self.draw_brush_radius
+            // Live pointer input would get in the way of a replay
+            WindowEvent::CursorMoved { position, .. } if self.replay.is_none() => {
+                self.handle_input(InputEvent::CursorMoved {
+                    x: position.x,
+                    y: position.y,
+                });
+            }
+            WindowEvent::MouseWheel { delta, .. } if self.replay.is_none() => {
+                let y = match delta {
+                    winit::event::MouseScrollDelta::LineDelta(_, y) => y,
+                    winit::event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
                 };
-
-                if let Some(last_pos) = self.last_position {
-                    let dx = current_pos.0 - last_pos.0;
-                    let dy = current_pos.1 - last_pos.1;
-                    let distance_sq = dx * dx + dy * dy;
-
-                    // Only draw an interpolated line if the mouse moved significantly
-                    if distance_sq > (current_brush_radius * current_brush_radius / 2) as i32 {
-                        self.draw_interpolated_line(
-                            last_pos.0,
-                            last_pos.1,
-                            current_pos.0,
-                            current_pos.1,
-                            color,
-                        );
-                    } else {
-                        // If movement is small, just draw a circle at the current position
-                        // This helps fill small gaps and acts as the "click" drawing
-                        self.draw_circle_fast(
-                            current_pos.0,
-                            current_pos.1,
-                            current_brush_radius,
-                            color,
-                        );
-                    }
-                } else {
-                    // First point of a new stroke (or a single click)
-                    self.draw_circle_fast(
-                        current_pos.0,
-                        current_pos.1,
-                        current_brush_radius,
-                        color,
-                    );
-                }
-
-                self.last_position = Some(current_pos);
+                self.handle_input(InputEvent::MouseWheel { y });
             }
-            WindowEvent::MouseWheel { delta, .. } => match delta {
-                winit::event::MouseScrollDelta::LineDelta(_, y) => {
-                    if y > 0.0 {
-                        if self.draw_state == DrawState::Erasing {
-                            self.erase_brush_radius = (self.erase_brush_radius + 1).min(50);
-                        } else {
-                            self.draw_brush_radius = (self.draw_brush_radius + 1).min(20);
-                        }
-                    } else if y < 0.0 {
-                        if self.draw_state == DrawState::Erasing {
-                            self.erase_brush_radius = (self.erase_brush_radius - 1).max(1);
-                        } else {
-                            self.draw_brush_radius = (self.draw_brush_radius - 1).max(1);
-                        }
-                    }
+            WindowEvent::MouseInput { state, button, .. } if self.replay.is_none() => {
+                self.handle_input(InputEvent::MouseInput {
+                    button: button.into(),
+                    pressed: state == ElementState::Pressed,
+                });
+            }
+            WindowEvent::RedrawRequested => {
+                // Render whatever just came into view. The whole window is only
+                // drawn again when something changed since the last present or
+                // the buffer doesn't hold that frame anymore
+                self.prepare_canvas();
+                if !self.canvas.take_dirty().is_empty() {
+                    self.needs_repaint = true;
                 }
-                winit::event::MouseScrollDelta::PixelDelta(pos) => {
-                    if pos.y > 0.0 {
-                        if self.draw_state == DrawState::Erasing {
-                            self.erase_brush_radius = (self.erase_brush_radius + 1).min(50);
-                        } else {
-                            self.draw_brush_radius = (self.draw_brush_radius + 1).min(20);
-                        }
-                    } else if pos.y < 0.0 {
-                        if self.draw_state == DrawState::Erasing {
-                            self.erase_brush_radius = (self.erase_brush_radius - 1).max(1);
-                        } else {
-                            self.draw_brush_radius = (self.draw_brush_radius - 1).max(1);
+                let PhysicalSize { width, height } = self.inner_size;
+                let marker = self.snap_target();
+                let tags = self.peer_tags();
+                if let Some(surface) = self.surface.as_mut()
+                    && let Ok(mut buffer) = surface.buffer_mut()
+                {
+                    if buffer.len() != (width * height) as usize {
+                        // This might happen if `resize` is called but `RedrawRequested` comes before the new buffer is ready.
+                        // In this case, we re-initialize the buffer to black.
+                        for pixel in buffer.iter_mut() {
+                            *pixel = Color::Black as u32;
                         }
+                        let _ = buffer.present();
+                        return;
                     }
-                }
-            },
-            WindowEvent::MouseInput { state, button, .. } => {
-                if state == ElementState::Pressed {
-                    if let Some(window) = &self.window {
-                        // Get the current cursor position when the mouse button is pressed
-                        if let Some(cursor_position) = self.position {
-                            let current_pos = (cursor_position.0 as i32, cursor_position.1 as i32);
-                            let color = if button == MouseButton::Right {
-                                Color::Black
-                            } else {
-                                self.pointer_color
-                            };
-                            let current_brush_radius = if button == MouseButton::Right {
-                                self.erase_brush_radius
-                            } else {
-                                self.draw_brush_radius
-                            };
-
-                            // Save state before drawing the initial dot
-                            self.save_state();
-
-                            // Draw a circle at the clicked position immediately
-                            self.draw_circle_fast(
-                                current_pos.0,
-                                current_pos.1,
-                                current_brush_radius,
-                                color,
-                            );
-
-                            self.last_position = Some(current_pos);
+                    if !self.needs_repaint && buffer.age() == 1 {
+                        if let Some(frames) = &self.frames {
+                            frames.update(width, height, &buffer);
                         }
+                        self.last_damage = Some(Vec::new());
+                        let _ = buffer.present(); // Same frame, shown again
+                        return;
+                    }
 
-                        match button {
-                            MouseButton::Left => {
-                                self.draw_state = DrawState::Drawing;
-                            }
-                            MouseButton::Right => {
-                                self.draw_state = DrawState::Erasing;
-                            }
-                            _ => {}
-                        }
+                    self.snap_marker = marker;
+                    self.tag_rects = tags
+                        .iter()
+                        .filter_map(|(point, name)| overlay::tag_rect(width, height, *point, name))
+                        .collect();
+
+                    let rect = (0, 0, width, height);
+                    let origin = self.view.origin();
+                    self.background
+                        .render(&mut buffer, width, rect, origin, self.view.zoom, true);
+                    self.canvas.composite_onto(&mut buffer, width, rect, origin);
+                    // The pasted image is only shown on top until it gets committed
+                    if let Some(floating_image) = &self.floating_image {
+                        floating_image.composite(&mut buffer, width, height);
                     }
-                } else {
-                    self.draw_state = DrawState::Idle;
-                    self.last_position = None;
-                }
-            }
-            WindowEvent::RedrawRequested => {
-                // This is where you draw your `self.pixels` to the `softbuffer`
-                if let Some(surface) = self.surface.as_mut() {
-                    if let Ok(mut buffer) = surface.buffer_mut() {
-                        let size = (self.inner_size.width * self.inner_size.height) as usize;
-                        if buffer.len() == size {
-                            buffer.copy_from_slice(&self.pixels); // Copy all pixels from your buffer
-                            let _ = buffer.present(); // Full present
-                        } else {
-                            // This might happen if `resize` is called but `RedrawRequested` comes before the new buffer is ready.
-                            // In this case, we re-initialize the buffer to black.
-                            for pixel in buffer.iter_mut() {
-                                *pixel = Color::Black as u32;
-                            }
-                            let _ = buffer.present();
-                        }
+                    if let Some(selection) = &self.selection {
+                        selection.draw_outline(&mut buffer, width, height);
+                    }
+                    if self.pages.len() > 1 {
+                        overlay::draw_label(
+                            &mut buffer,
+                            width,
+                            height,
+                            &format!("{}/{}", self.current_page + 1, self.pages.len()),
+                        );
+                    }
+                    if let Some(marker) = self.snap_marker {
+                        overlay::draw_marker(&mut buffer, width, height, marker);
+                    }
+                    for (point, name) in &tags {
+                        overlay::draw_tag(&mut buffer, width, height, *point, name);
+                    }
+                    if let Some(frames) = &self.frames {
+                        frames.update(width, height, &buffer);
                     }
+                    #[cfg(feature = "http")]
+                    if let Some(http) = &self.http {
+                        http.changed();
+                    }
+                    self.needs_repaint = false;
+                    self.last_damage = None;
+                    let _ = buffer.present(); // Full present
                 }
             }
             _ => {}
@@ -695,10 +2017,180 @@ impl ApplicationHandler for DrawOnScreen {
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let event_loop = EventLoop::new()?;
+    let command = match cli::parse(std::env::args_os().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // Commands that don't need a window are done here. Starting the window
+    // while it is already open brings the running one to the front instead
+    let (mut options, request) = match command {
+        cli::Command::Run(options) => (*options, Request::Show),
+        cli::Command::Remote(request) => (cli::Options::default(), request),
+        cli::Command::Render {
+            input,
+            output,
+            format,
+            scale,
+        } => {
+            let output = output.unwrap_or_else(|| input.with_extension(format.extension()));
+            if let Err(err) = headless::render(&input, &output, format, scale) {
+                eprintln!("Failed to render {}: {err}", input.display());
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        cli::Command::Timelapse {
+            input,
+            output,
+            format,
+            fps,
+            duration,
+            scale,
+        } => {
+            let output = output.unwrap_or_else(|| input.with_extension(format.extension()));
+            if let Err(err) = headless::timelapse(&input, &output, format, fps, duration, scale) {
+                eprintln!("Failed to render {}: {err}", input.display());
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        cli::Command::Version => {
+            println!("drawonscreen_rust {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        cli::Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+    };
+
+    // Without a runtime directory there is no telling, so just start. Any
+    // number of instances can share a canvas, next to the one that got the lock
+    let given = options.given();
+    let share = options.share.take();
+    let bind = options.bind.take().unwrap_or(Ipv4Addr::LOCALHOST.into());
+    let http_port = options.http.take();
+    #[cfg(not(feature = "http"))]
+    if http_port.is_some() {
+        eprintln!("--http needs a build with the http feature (cargo build --features http)");
+        std::process::exit(1);
+    }
+    let name = options.name.take();
+    let instance_lock = match control::lock_instance() {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) if share.is_some() => None,
+        Ok(None) => {
+            // The running instance keeps its own settings and canvas
+            if !given.is_empty() {
+                eprintln!(
+                    "Draw On Screen is already running, ignoring {}",
+                    given.join(", ")
+                );
+            }
+            forward(&request)
+        }
+        Err(_) => None,
+    };
+    if !matches!(request, Request::Show | Request::ToggleVisibility) {
+        eprintln!("Draw On Screen is not running");
+        std::process::exit(1);
+    }
+
+    let event_loop = EventLoop::<UserEvent>::with_user_event().build()?;
     let mut draw_on_screen = DrawOnScreen::default();
+    if let Err(err) = draw_on_screen.configure(options, event_loop.create_proxy()) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+
+    // Scripting stays with the instance that holds the lock
+    if instance_lock.is_some() || share.is_none() {
+        match control::listen(event_loop.create_proxy()) {
+            Ok(socket) => draw_on_screen.control_socket = Some(socket),
+            Err(err) => eprintln!("Control socket is not available: {err}"),
+        }
+        match dbus::serve(event_loop.create_proxy()) {
+            Ok(service) => draw_on_screen.dbus = Some(service),
+            Err(err) => eprintln!("D-Bus is not available: {err}"),
+        }
+    }
+    #[cfg(feature = "http")]
+    if let Some(port) = http_port {
+        match http::serve(port, event_loop.create_proxy()) {
+            Ok(server) => draw_on_screen.http = Some(server),
+            Err(err) => {
+                eprintln!("Failed to serve HTTP on port {port}: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(share) = share {
+        let name = name
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_else(|| "peer".to_string());
+        let proxy = event_loop.create_proxy();
+        let shared = match &share {
+            cli::Share::Host(port) | cli::Share::Serve(port) => {
+                let read_only = matches!(share, cli::Share::Serve(_));
+                TcpListener::bind((bind, *port))
+                    .map(|listener| (shared::host(listener, name, read_only, proxy), None))
+            }
+            cli::Share::Join(address) | cli::Share::View(address) => {
+                let read_only = matches!(share, cli::Share::View(_));
+                shared::join(address, name, read_only, proxy)
+                    .map(|(sharing, page)| (sharing, Some(page)))
+            }
+        };
+        match shared {
+            Ok((sharing, page)) => {
+                draw_on_screen.sharing = Some(sharing);
+                draw_on_screen.pending_page = page;
+            }
+            Err(err) => {
+                match share {
+                    cli::Share::Host(port) | cli::Share::Serve(port) => {
+                        eprintln!("Failed to share on port {port}: {err}")
+                    }
+                    cli::Share::Join(address) | cli::Share::View(address) => {
+                        eprintln!("Failed to join {address}: {err}")
+                    }
+                }
+                std::process::exit(1);
+            }
+        }
+    }
 
     event_loop.run_app(&mut draw_on_screen)?;
 
     Ok(())
 }
+
+fn is_wayland(window: &Window) -> bool {
+    window
+        .window_handle()
+        .is_ok_and(|handle| matches!(handle.as_raw(), RawWindowHandle::Wayland(_)))
+}
+
+// Pass a command to the running instance and exit with how it went
+fn forward(request: &Request) -> ! {
+    match control::send(request) {
+        Ok(reply) if reply.ok => {
+            if let Some(path) = reply.path {
+                println!("Saved {path}");
+            }
+            std::process::exit(0);
+        }
+        Ok(reply) => {
+            eprintln!("{}", reply.error.unwrap_or_default());
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Failed to reach the running instance: {err}");
+            std::process::exit(1);
+        }
+    }
+}
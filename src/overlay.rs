@@ -0,0 +1,243 @@
+// On-screen indicators drawn on top of the canvas when presenting. They are
+// never part of the drawing itself
+use crate::blend;
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+const SCALE: u32 = 3;
+const PADDING: u32 = 6;
+const MARGIN: u32 = 12;
+const MARKER_RADIUS: i32 = 4;
+const MARKER_WIDTH: u32 = 2;
+const TAG_OFFSET: i32 = 8; // From the point a tag belongs to
+
+// 3x5 bitmap glyphs, one row per entry, most significant of the 3 bits on the
+// left. Letters are all upper case
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        _ => [0; 5],
+    }
+}
+
+fn fill_rect(
+    target: &mut [u32],
+    width: u32,
+    height: u32,
+    rect: (u32, u32, u32, u32),
+    color: u32,
+    alpha: f32,
+) {
+    let (x, y, w, h) = rect;
+    let end_x = (x + w).min(width);
+    if x >= end_x {
+        return;
+    }
+
+    let coverage = vec![alpha; (end_x - x) as usize];
+    for row in y..(y + h).min(height) {
+        let start = (row * width + x) as usize;
+        blend::source_over(&mut target[start..start + coverage.len()], color, &coverage);
+    }
+}
+
+// Width and height of the box `text` gets drawn on
+fn box_size(text: &str) -> Option<(u32, u32)> {
+    let columns = text.chars().count() as u32;
+    if columns == 0 {
+        return None;
+    }
+
+    let text_width = columns * (GLYPH_WIDTH + 1) * SCALE - SCALE;
+    let text_height = GLYPH_HEIGHT * SCALE;
+    Some((text_width + PADDING * 2, text_height + PADDING * 2))
+}
+
+// Where `draw_label` puts its box as (x, y, width, height), `None` when the
+// window is too small to show it
+pub fn label_rect(width: u32, height: u32, text: &str) -> Option<(u32, u32, u32, u32)> {
+    let (box_width, box_height) = box_size(text)?;
+    if box_width + MARGIN > width || box_height + MARGIN > height {
+        return None;
+    }
+
+    Some((
+        width - MARGIN - box_width,
+        height - MARGIN - box_height,
+        box_width,
+        box_height,
+    ))
+}
+
+// Text on a translucent box in the bottom right corner
+pub fn draw_label(target: &mut [u32], width: u32, height: u32, text: &str) {
+    if let Some(rect) = label_rect(width, height, text) {
+        draw_text_box(target, width, height, rect, text);
+    }
+}
+
+// Where `draw_tag` puts its box for a tag on the screen point `(x, y)`, pushed
+// back into the window near its edges. `None` when the point is off screen or
+// the window too small
+pub fn tag_rect(
+    width: u32,
+    height: u32,
+    (x, y): (i32, i32),
+    text: &str,
+) -> Option<(u32, u32, u32, u32)> {
+    let (box_width, box_height) = box_size(text)?;
+    if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+        return None;
+    }
+    if box_width > width || box_height > height {
+        return None;
+    }
+    Some((
+        ((x + TAG_OFFSET) as u32).min(width - box_width),
+        ((y + TAG_OFFSET) as u32).min(height - box_height),
+        box_width,
+        box_height,
+    ))
+}
+
+// A name next to a point, like the one attributing strokes on a shared canvas
+pub fn draw_tag(target: &mut [u32], width: u32, height: u32, point: (i32, i32), text: &str) {
+    if let Some(rect) = tag_rect(width, height, point, text) {
+        draw_text_box(target, width, height, rect, text);
+    }
+}
+
+fn draw_text_box(
+    target: &mut [u32],
+    width: u32,
+    height: u32,
+    (box_x, box_y, box_width, box_height): (u32, u32, u32, u32),
+    text: &str,
+) {
+    fill_rect(
+        target,
+        width,
+        height,
+        (box_x, box_y, box_width, box_height),
+        0x000000,
+        0.6,
+    );
+
+    for (index, c) in text.chars().enumerate() {
+        let glyph_x = box_x + PADDING + index as u32 * (GLYPH_WIDTH + 1) * SCALE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    fill_rect(
+                        target,
+                        width,
+                        height,
+                        (
+                            glyph_x + column * SCALE,
+                            box_y + PADDING + row as u32 * SCALE,
+                            SCALE,
+                            SCALE,
+                        ),
+                        0xfafafa,
+                        1.0,
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Where `draw_marker` draws around `center` as (x, y, width, height), clipped
+// to the window, `None` when none of it is visible
+pub fn marker_rect(width: u32, height: u32, (x, y): (i32, i32)) -> Option<(u32, u32, u32, u32)> {
+    let (left, top) = ((x - MARKER_RADIUS).max(0), (y - MARKER_RADIUS).max(0));
+    let right = (x + MARKER_RADIUS + 1).min(width as i32);
+    let bottom = (y + MARKER_RADIUS + 1).min(height as i32);
+    (left < right && top < bottom).then_some((
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    ))
+}
+
+// A small hollow square showing the grid point a stroke will snap to
+pub fn draw_marker(target: &mut [u32], width: u32, height: u32, (x, y): (i32, i32)) {
+    let size = (MARKER_RADIUS * 2 + 1) as u32;
+    let (left, top) = (x - MARKER_RADIUS, y - MARKER_RADIUS);
+    let sides = [
+        (left, top, size, MARKER_WIDTH),
+        (
+            left,
+            y + MARKER_RADIUS + 1 - MARKER_WIDTH as i32,
+            size,
+            MARKER_WIDTH,
+        ),
+        (
+            left,
+            top + MARKER_WIDTH as i32,
+            MARKER_WIDTH,
+            size - MARKER_WIDTH * 2,
+        ),
+        (
+            x + MARKER_RADIUS + 1 - MARKER_WIDTH as i32,
+            top + MARKER_WIDTH as i32,
+            MARKER_WIDTH,
+            size - MARKER_WIDTH * 2,
+        ),
+    ];
+    for (side_x, side_y, side_width, side_height) in sides {
+        // `fill_rect` only clips the far edges
+        let clipped_x = side_x.max(0);
+        let clipped_y = side_y.max(0);
+        let side_width = (side_x + side_width as i32 - clipped_x).max(0) as u32;
+        let side_height = (side_y + side_height as i32 - clipped_y).max(0) as u32;
+        fill_rect(
+            target,
+            width,
+            height,
+            (clipped_x as u32, clipped_y as u32, side_width, side_height),
+            0x38bdf8,
+            0.9,
+        );
+    }
+}
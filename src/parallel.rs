@@ -0,0 +1,62 @@
+// Splitting rasterization work over threads. Jobs only ever write to pixels
+// they own and do so in the same order as a single thread would, so the output
+// never depends on how the work was divided
+use std::{cell::Cell, thread};
+
+thread_local! {
+    // Pinned split for this thread, 0 for one worker per core
+    static WORKERS: Cell<usize> = const { Cell::new(0) };
+}
+
+// How many ways to split work, at least 1
+pub fn workers() -> usize {
+    match WORKERS.get() {
+        0 => thread::available_parallelism().map_or(1, |count| count.get()),
+        workers => workers,
+    }
+}
+
+// Run `f` with work split exactly `workers` ways on this thread, so tests can
+// compare splits whatever the machine
+#[cfg(test)]
+pub fn with_workers<R>(workers: usize, f: impl FnOnce() -> R) -> R {
+    let previous = WORKERS.replace(workers.max(1));
+    let result = f();
+    WORKERS.set(previous);
+    result
+}
+
+// `job` applied to every item, results in the order of `items`. Items are dealt
+// out in contiguous runs, one per worker, with the first run done on the calling
+// thread
+pub fn map<T: Send, R: Send>(items: Vec<T>, job: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let workers = workers().min(items.len());
+    if workers <= 1 {
+        return items.into_iter().map(job).collect();
+    }
+
+    let run_length = items.len().div_ceil(workers);
+    let mut runs: Vec<Vec<T>> = Vec::with_capacity(workers);
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        runs.push(items.by_ref().take(run_length).collect());
+    }
+
+    let job = &job;
+    thread::scope(|scope| {
+        let mut runs = runs.into_iter();
+        let first = runs.next().unwrap_or_default();
+        let handles: Vec<_> = runs
+            .map(|run| scope.spawn(move || run.into_iter().map(job).collect::<Vec<R>>()))
+            .collect();
+
+        let mut results: Vec<R> = first.into_iter().map(job).collect();
+        for handle in handles {
+            match handle.join() {
+                Ok(run) => results.extend(run),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        results
+    })
+}
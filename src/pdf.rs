@@ -0,0 +1,316 @@
+// Minimal PDF writer, just enough for whiteboard pages: vector strokes,
+// embedded images and full page rasters. Streams are zlib compressed.
+use std::fmt::Write;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+use crate::{
+    background::{Background, Pattern},
+    document::Element,
+    image::Image,
+    view::View,
+};
+
+// A page as it appears on screen
+pub enum PageContent<'a> {
+    // Its document seen through its view, as paths and images
+    Vector { elements: &'a [Element], view: View },
+    // Rendered, 0xAARRGGBB at one pixel per point, for what paths can't express
+    Raster(Vec<u32>),
+}
+
+// Patterns and background images only exist as pixels, so pages on them are
+// exported as rendered. So are erased pages on anything but a plain solid
+// color, since erasers are painted in the background color
+pub fn can_draw_vector(elements: &[Element], background: &Background) -> bool {
+    let has_pixels = !matches!(background.pattern, Pattern::Solid | Pattern::Transparent)
+        || background.image.is_some();
+    let has_erasers = elements
+        .iter()
+        .any(|element| matches!(element, Element::Stroke(stroke) if stroke.is_eraser));
+    !has_pixels && (background.pattern == Pattern::Solid || !has_erasers)
+}
+
+struct PdfWriter {
+    bytes: Vec<u8>,
+    offsets: Vec<usize>, // Byte offset of every object, object n at index n - 1
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        let mut bytes = b"%PDF-1.4\n".to_vec();
+        // Binary marker so tools don't treat the file as text
+        bytes.extend_from_slice(b"%\xE2\xE3\xCF\xD3\n");
+
+        Self {
+            bytes,
+            offsets: Vec::new(),
+        }
+    }
+
+    // Reserve an object number so it can be referenced before it is written
+    fn reserve(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn object(&mut self, id: usize, body: &str) {
+        self.offsets[id - 1] = self.bytes.len();
+        self.bytes
+            .extend_from_slice(format!("{id} 0 obj\n{body}\nendobj\n").as_bytes());
+    }
+
+    fn stream(&mut self, id: usize, dictionary: &str, data: &[u8]) {
+        let compressed = compress_to_vec_zlib(data, 6);
+
+        self.offsets[id - 1] = self.bytes.len();
+        self.bytes.extend_from_slice(
+            format!(
+                "{id} 0 obj\n<< {dictionary} /Filter /FlateDecode /Length {} >>\nstream\n",
+                compressed.len()
+            )
+            .as_bytes(),
+        );
+        self.bytes.extend_from_slice(&compressed);
+        self.bytes.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+
+    // Image XObject, with a soft mask when any pixel is not fully opaque
+    fn image(&mut self, width: u32, height: u32, pixels: &[u32]) -> usize {
+        let id = self.reserve();
+
+        let rgb: Vec<u8> = pixels
+            .iter()
+            .flat_map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8])
+            .collect();
+
+        let mut dictionary = format!(
+            "/Type /XObject /Subtype /Image /Width {width} /Height {height} /ColorSpace /DeviceRGB /BitsPerComponent 8"
+        );
+
+        if pixels.iter().any(|&p| p >> 24 != 0xFF) {
+            let mask = self.reserve();
+            let alpha: Vec<u8> = pixels.iter().map(|&p| (p >> 24) as u8).collect();
+            self.stream(
+                mask,
+                &format!(
+                    "/Type /XObject /Subtype /Image /Width {width} /Height {height} /ColorSpace /DeviceGray /BitsPerComponent 8"
+                ),
+                &alpha,
+            );
+            let _ = write!(dictionary, " /SMask {mask} 0 R");
+        }
+
+        self.stream(id, &dictionary, &rgb);
+        id
+    }
+
+    fn finish(mut self, root: usize) -> Vec<u8> {
+        let xref = self.bytes.len();
+
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            let _ = writeln!(table, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root {root} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            self.offsets.len() + 1
+        );
+
+        self.bytes.extend_from_slice(table.as_bytes());
+        self.bytes
+    }
+}
+
+// PDF colors are 0..1 floats
+fn rgb(color: u32) -> String {
+    format!(
+        "{:.3} {:.3} {:.3}",
+        ((color >> 16) & 0xFF) as f32 / 255.0,
+        ((color >> 8) & 0xFF) as f32 / 255.0,
+        (color & 0xFF) as f32 / 255.0
+    )
+}
+
+// One PDF point per window pixel. Content streams flip the y axis and then apply
+// the page's view, so all coordinates can be written in world units as they are
+// in the document. Vector pages are filled with `background` first, eraser
+// strokes are painted in the same color. Raster pages are one full page image
+pub fn write_pdf(
+    pages: &[PageContent],
+    width: u32,
+    height: u32,
+    background: Option<u32>,
+) -> Vec<u8> {
+    let mut writer = PdfWriter::new();
+
+    let catalog = writer.reserve();
+    let page_tree = writer.reserve();
+    let mut page_ids = Vec::new();
+
+    for page in pages {
+        let mut content = format!("1 0 0 -1 0 {height} cm\n");
+        let mut images = Vec::new();
+
+        let (elements, view) = match page {
+            PageContent::Vector { elements, view } => {
+                if let Some(background) = background {
+                    let _ = writeln!(content, "{} rg 0 0 {width} {height} re f", rgb(background));
+                }
+                (*elements, *view)
+            }
+            PageContent::Raster(pixels) => {
+                images.push(writer.image(width, height, pixels));
+                let _ = writeln!(content, "q {width} 0 0 -{height} 0 {height} cm /Im0 Do Q");
+                (&[][..], View::default())
+            }
+        };
+        let _ = writeln!(
+            content,
+            "{zoom} 0 0 {zoom} {} {} cm",
+            -view.offset_x * view.zoom,
+            -view.offset_y * view.zoom,
+            zoom = view.zoom
+        );
+        // Round caps and joins, like the circles stamped by the rasterizer
+        content.push_str("1 J 1 j\n");
+
+        for element in elements.iter() {
+            match element {
+                Element::Stroke(stroke) => {
+                    let Some(&(first_x, first_y)) = stroke.points.first() else {
+                        continue;
+                    };
+                    let color = if stroke.is_eraser {
+                        // Viewers show transparent pages as white paper
+                        background.unwrap_or(0xFFFFFF)
+                    } else {
+                        stroke.color
+                    };
+
+                    let _ = writeln!(
+                        content,
+                        "{} RG {} w {first_x} {first_y} m",
+                        rgb(color),
+                        stroke.radius * 2.0 + 1.0
+                    );
+                    if stroke.points.len() == 1 {
+                        // A zero length segment with round caps renders as a dot
+                        let _ = writeln!(content, "{first_x} {first_y} l");
+                    }
+                    for &(x, y) in &stroke.points[1..] {
+                        let _ = writeln!(content, "{x} {y} l");
+                    }
+                    content.push_str("S\n");
+                }
+                Element::Image {
+                    x,
+                    y,
+                    width: image_width,
+                    height: image_height,
+                    image,
+                } => {
+                    let Image {
+                        width: source_width,
+                        height: source_height,
+                        pixels,
+                    } = image.as_ref();
+                    let id = writer.image(*source_width, *source_height, pixels);
+                    let _ = writeln!(
+                        content,
+                        "q {image_width} 0 0 -{image_height} {x} {} cm /Im{} Do Q",
+                        y + image_height,
+                        images.len()
+                    );
+                    images.push(id);
+                }
+            }
+        }
+
+        let content_id = writer.reserve();
+        writer.stream(content_id, "", content.as_bytes());
+
+        let mut resources = String::from("<< /XObject << ");
+        for (index, id) in images.iter().enumerate() {
+            let _ = write!(resources, "/Im{index} {id} 0 R ");
+        }
+        resources.push_str(">> >>");
+
+        let page_id = writer.reserve();
+        writer.object(
+            page_id,
+            &format!(
+                "<< /Type /Page /Parent {page_tree} 0 R /MediaBox [0 0 {width} {height}] /Resources {resources} /Contents {content_id} 0 R >>"
+            ),
+        );
+        page_ids.push(page_id);
+    }
+
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{id} 0 R")).collect();
+    writer.object(
+        page_tree,
+        &format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            page_ids.len()
+        ),
+    );
+    writer.object(
+        catalog,
+        &format!("<< /Type /Catalog /Pages {page_tree} 0 R >>"),
+    );
+
+    writer.finish(catalog)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::document::Stroke;
+
+    fn eraser() -> Element {
+        Element::Stroke(Stroke {
+            color: 0,
+            radius: 5.0,
+            is_eraser: true,
+            points: vec![(1.0, 1.0)],
+            id: None,
+        })
+    }
+
+    fn background(pattern: Pattern) -> Background {
+        Background {
+            pattern,
+            ..Background::default()
+        }
+    }
+
+    #[test]
+    fn plain_backgrounds_stay_vector() {
+        assert!(can_draw_vector(&[eraser()], &background(Pattern::Solid)));
+        assert!(can_draw_vector(&[], &background(Pattern::Transparent)));
+        assert!(!can_draw_vector(
+            &[eraser()],
+            &background(Pattern::Transparent)
+        ));
+    }
+
+    #[test]
+    fn patterns_and_images_are_rendered() {
+        for pattern in [Pattern::Grid, Pattern::Dots, Pattern::Lines] {
+            assert!(!can_draw_vector(&[], &background(pattern)));
+        }
+        let image = Background {
+            image: Some(Arc::new(Image {
+                width: 1,
+                height: 1,
+                pixels: vec![0xFFFFFFFF],
+            })),
+            ..Background::default()
+        };
+        assert!(!can_draw_vector(&[], &image));
+    }
+}
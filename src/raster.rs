@@ -0,0 +1,397 @@
+// Software rasterizer shared by the window and everything that renders a
+// document without one (exporters, headless rendering)
+use crate::{
+    blend,
+    document::{Element, Stroke},
+    image::{self, Image},
+    parallel,
+    view::{self, View},
+};
+
+// What a stamp does to the pixels under it
+#[derive(Clone, Copy)]
+pub enum Paint {
+    Color(u32),
+    Erase, // Back to transparent, letting the background through
+}
+
+// Something strokes and images can be painted into, addressed in canvas pixels
+// (see `view::to_canvas`)
+pub trait Target {
+    fn stamp_circle(&mut self, center: (i32, i32), radius: i32, paint: Paint);
+    fn draw_image(&mut self, image: &Image, placement: (i32, i32, u32, u32));
+
+    // A run of circles stamped one after the other, e.g. along a line. Targets
+    // that can split the work up override this
+    fn stamp_circles(&mut self, centers: &[(i32, i32)], radius: i32, paint: Paint) {
+        for &center in centers {
+            self.stamp_circle(center, radius, paint);
+        }
+    }
+}
+
+// A flat buffer holding the `width` x `height` canvas pixels starting at `origin`
+pub struct Pixels<'a> {
+    pub pixels: &'a mut [u32],
+    pub width: u32,
+    pub height: u32,
+    pub origin: (i32, i32),
+}
+
+impl Target for Pixels<'_> {
+    fn stamp_circle(&mut self, (x, y): (i32, i32), radius: i32, paint: Paint) {
+        stamp_circle(
+            self.pixels,
+            self.width as i32,
+            self.height as i32,
+            (x - self.origin.0, y - self.origin.1),
+            radius,
+            paint,
+        );
+    }
+
+    fn draw_image(&mut self, image: &Image, (x, y, width, height): (i32, i32, u32, u32)) {
+        image::draw_scaled(
+            image,
+            (x - self.origin.0, y - self.origin.1, width, height),
+            self.pixels,
+            self.width,
+            self.height,
+        );
+    }
+}
+
+// Pixels whose coverage is computed before handing them to the blender
+const COVERAGE_RUN: usize = 64;
+
+// Antialiased circle with distance-based alpha
+pub fn stamp_circle(
+    pixels: &mut [u32],
+    width: i32,
+    height: i32,
+    (cx, cy): (i32, i32),
+    radius: i32,
+    paint: Paint,
+) {
+    let radius_f = radius as f32;
+
+    // Early bounds check with antialiasing margin
+    let aa_radius = radius + 1;
+    if cx + aa_radius < 0
+        || cx - aa_radius >= width
+        || cy + aa_radius < 0
+        || cy - aa_radius >= height
+    {
+        return;
+    }
+
+    // Optimize bounds for the loop with antialiasing
+    let start_y = (-aa_radius).max(-cy);
+    let end_y = aa_radius.min(height - cy - 1);
+    let start_x = (-aa_radius).max(-cx);
+    let end_x = aa_radius.min(width - cx - 1);
+
+    // Coverage is worked out for a run of pixels at a time and then blended in
+    // one go
+    let mut coverage = [0.0; COVERAGE_RUN];
+    for dy in start_y..=end_y {
+        let row = ((cy + dy) * width) as usize;
+
+        let mut run_start = start_x;
+        while run_start <= end_x {
+            let run_length = ((end_x - run_start + 1) as usize).min(COVERAGE_RUN);
+
+            for (offset, alpha) in coverage[..run_length].iter_mut().enumerate() {
+                let dx = run_start + offset as i32;
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+
+                *alpha = if distance <= radius_f {
+                    1.0 // Full opacity inside the circle
+                } else {
+                    // Antialiasing: fade out at the edge, nothing past one pixel
+                    1.0 - (distance - radius_f)
+                };
+            }
+
+            let start = row + (cx + run_start) as usize;
+            let run = &mut pixels[start..start + run_length];
+            match paint {
+                Paint::Color(color) => blend::source_over(run, color, &coverage[..run_length]),
+                Paint::Erase => blend::erase(run, &coverage[..run_length]),
+            }
+            run_start += run_length as i32;
+        }
+    }
+}
+
+// Interpolated line between two points: Bresenham, with an antialiased circle
+// stamped at every point
+pub fn draw_line(
+    target: &mut impl Target,
+    start: (i32, i32),
+    end: (i32, i32),
+    radius: i32,
+    paint: Paint,
+) {
+    target.stamp_circles(&line_points(start, end), radius, paint);
+}
+
+fn line_points((x0, y0): (i32, i32), (x1, y1): (i32, i32)) -> Vec<(i32, i32)> {
+    let mut points = Vec::new();
+
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+
+    let mut err = dx + dy;
+    let mut x = x0;
+    let mut y = y0;
+
+    loop {
+        points.push((x, y));
+
+        if x == x1 && y == y1 {
+            break;
+        }
+
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+
+    points
+}
+
+// Draw one cursor sample of a stroke, given the sample before it
+pub fn draw_stroke_step(
+    target: &mut impl Target,
+    last: Option<(i32, i32)>,
+    current: (i32, i32),
+    radius: i32,
+    paint: Paint,
+) {
+    if let Some(last) = last {
+        let dx = current.0 - last.0;
+        let dy = current.1 - last.1;
+        let distance_sq = dx * dx + dy * dy;
+
+        // Only draw an interpolated line if the mouse moved significantly
+        if distance_sq > radius * radius / 2 {
+            draw_line(target, last, current, radius, paint);
+            return;
+        }
+    }
+
+    // If movement is small, just draw a circle at the current position. This
+    // helps fill small gaps and acts as the "click" drawing
+    target.stamp_circle(current, radius, paint);
+}
+
+// Draw the `index`th sample of a recorded stroke, exactly the way it is drawn
+// while the stroke is being made
+pub fn draw_stroke_point(target: &mut impl Target, zoom: f32, stroke: &Stroke, index: usize) {
+    let last = index
+        .checked_sub(1)
+        .map(|last| view::to_canvas(stroke.points[last], zoom));
+    draw_stroke_step(
+        target,
+        last,
+        view::to_canvas(stroke.points[index], zoom),
+        view::to_canvas_length(stroke.radius, zoom),
+        if stroke.is_eraser {
+            Paint::Erase
+        } else {
+            Paint::Color(stroke.color)
+        },
+    );
+}
+
+pub fn draw_element(target: &mut impl Target, zoom: f32, element: &Element) {
+    match element {
+        Element::Stroke(stroke) => {
+            for index in 0..stroke.points.len() {
+                draw_stroke_point(target, zoom, stroke, index);
+            }
+        }
+        Element::Image {
+            x,
+            y,
+            width,
+            height,
+            image,
+        } => target.draw_image(image, image_placement((*x, *y, *width, *height), zoom)),
+    }
+}
+
+fn image_placement((x, y, width, height): (f32, f32, f32, f32), zoom: f32) -> (i32, i32, u32, u32) {
+    let (x, y) = view::to_canvas((x, y), zoom);
+    (
+        x,
+        y,
+        view::to_canvas_length(width, zoom).max(1) as u32,
+        view::to_canvas_length(height, zoom).max(1) as u32,
+    )
+}
+
+// Canvas pixels an element can touch as inclusive (min x, min y, max x, max y)
+pub fn bounds(element: &Element, zoom: f32) -> (i32, i32, i32, i32) {
+    match element {
+        Element::Stroke(stroke) => {
+            // Circles reach one pixel past their radius with antialiasing
+            let reach = view::to_canvas_length(stroke.radius, zoom) + 1;
+            stroke.points.iter().fold(
+                (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+                |(min_x, min_y, max_x, max_y), &point| {
+                    let (x, y) = view::to_canvas(point, zoom);
+                    (
+                        min_x.min(x - reach),
+                        min_y.min(y - reach),
+                        max_x.max(x + reach),
+                        max_y.max(y + reach),
+                    )
+                },
+            )
+        }
+        Element::Image {
+            x,
+            y,
+            width,
+            height,
+            ..
+        } => {
+            let (x, y, width, height) = image_placement((*x, *y, *width, *height), zoom);
+            (x, y, x + width as i32 - 1, y + height as i32 - 1)
+        }
+    }
+}
+
+// Rasterize a whole document from scratch as seen through `view`, on a
+// transparent layer. The window is cut into horizontal bands that are rendered
+// in parallel
+pub fn render(elements: &[Element], view: &View, width: u32, height: u32) -> Vec<u32> {
+    let mut pixels = vec![0; (width * height) as usize];
+    if pixels.is_empty() {
+        return pixels;
+    }
+
+    let (origin_x, origin_y) = view.origin();
+    let element_bounds: Vec<_> = elements
+        .iter()
+        .map(|element| bounds(element, view.zoom))
+        .collect();
+
+    let band_height = height.div_ceil(parallel::workers() as u32);
+    let bands: Vec<(i32, &mut [u32])> = pixels
+        .chunks_mut((band_height * width) as usize)
+        .enumerate()
+        .map(|(index, band)| (origin_y + (index as u32 * band_height) as i32, band))
+        .collect();
+
+    parallel::map(bands, |(top, band)| {
+        let band_height = band.len() as u32 / width;
+        let mut target = Pixels {
+            pixels: band,
+            width,
+            height: band_height,
+            origin: (origin_x, top),
+        };
+        for (element, &(_, min_y, _, max_y)) in elements.iter().zip(&element_bounds) {
+            if max_y >= top && min_y < top + band_height as i32 {
+                draw_element(&mut target, view.zoom, element);
+            }
+        }
+    });
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiles::TiledCanvas;
+
+    const WIDTH: u32 = 600;
+    const HEIGHT: u32 = 400;
+
+    fn stroke(color: u32, radius: f32, is_eraser: bool, points: &[(f32, f32)]) -> Element {
+        Element::Stroke(Stroke {
+            color,
+            radius,
+            is_eraser,
+            points: points.to_vec(),
+            id: None,
+        })
+    }
+
+    fn drawing() -> Vec<Element> {
+        vec![
+            stroke(0x003b82f6, 12.0, false, &[(20.0, 30.0), (580.0, 370.0)]),
+            stroke(
+                0x00ef4444,
+                3.0,
+                false,
+                &[(250.0, 10.0), (262.0, 390.0), (10.0, 250.0)],
+            ),
+            stroke(0x0022c55e, 30.0, false, &[(300.0, 200.0)]),
+        ]
+    }
+
+    // Crosses the tile edges at 256 both ways and every band of a 7 way split
+    fn eraser() -> Element {
+        stroke(0, 50.0, true, &[(200.0, 40.0), (320.0, 360.0)])
+    }
+
+    #[test]
+    fn render_does_not_depend_on_the_split() {
+        let mut elements = drawing();
+        elements.push(eraser());
+        let view = View::default();
+
+        let single = parallel::with_workers(1, || render(&elements, &view, WIDTH, HEIGHT));
+        for workers in [2, 7, 16] {
+            let split = parallel::with_workers(workers, || render(&elements, &view, WIDTH, HEIGHT));
+            assert!(single == split, "{workers} workers differ from one");
+        }
+    }
+
+    #[test]
+    fn tiles_do_not_depend_on_the_split() {
+        let paint = |workers| {
+            parallel::with_workers(workers, || {
+                let mut canvas = TiledCanvas::new(1.0);
+                canvas.prepare(&drawing(), (0, 0, WIDTH, HEIGHT));
+                // Long enough to be stamped on several tiles in parallel
+                draw_element(&mut canvas, 1.0, &eraser());
+
+                let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+                canvas.composite_onto(&mut pixels, WIDTH, (0, 0, WIDTH, HEIGHT), (0, 0));
+                pixels
+            })
+        };
+
+        let single = paint(1);
+        for workers in [2, 7, 16] {
+            assert!(
+                single == paint(workers),
+                "{workers} workers differ from one"
+            );
+        }
+
+        // And painting the eraser on rendered tiles matches rendering it with them
+        let mut elements = drawing();
+        elements.push(eraser());
+        let mut rendered = vec![0; (WIDTH * HEIGHT) as usize];
+        blend::composite(
+            &mut rendered,
+            &parallel::with_workers(1, || render(&elements, &View::default(), WIDTH, HEIGHT)),
+        );
+        assert!(single == rendered, "tiles differ from a full render");
+    }
+}
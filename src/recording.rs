@@ -0,0 +1,374 @@
+// Recording the input that reaches the canvas and playing it back, for
+// presentation replays and for reproducing rendering bugs. A recording is JSON,
+// one object per line: a header with the window size and view at the start,
+// then every event with the milliseconds since then
+//
+//   {"version":1,"width":1280,"height":720,"offset_x":0.0,"offset_y":0.0,"zoom":1.0}
+//   {"time":0,"event":"tool","tool":"pen","color":16448250,"draw_radius":1,"erase_radius":3}
+//   {"time":0,"event":"mouse_input","button":"left","pressed":true}
+//   {"time":16,"event":"cursor_moved","x":412.0,"y":300.5}
+//   {"time":980,"event":"action","action":"undo"}
+//
+// Replayed events go through the same handlers as live ones, so replaying onto
+// the same session gives the same pixels
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, LineWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::event::MouseButton;
+
+use crate::{control::Tool, session, view::View};
+
+const VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum InputEvent {
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    MouseInput {
+        button: Button,
+        pressed: bool,
+    },
+    MouseWheel {
+        y: f32, // Lines or pixels, only the sign is used
+    },
+    Modifiers {
+        control: bool,
+        shift: bool,
+        space: bool, // Held to pan with the left button
+    },
+    // Written before a press whenever it changed since the last one, which
+    // covers keys, scripting and the wheel alike
+    Tool {
+        tool: Tool,
+        color: u32,
+        draw_radius: i32,
+        erase_radius: i32,
+    },
+    Action {
+        action: Action,
+    },
+}
+
+// Keyboard shortcuts that change the canvas. Pasting is left out: the
+// clipboard contents aren't part of a recording
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Clear,
+    Undo,
+    Redo,
+    NextPage,
+    PreviousPage,
+    InsertPage,
+    ResetView,
+    NextBackgroundColor,
+    NextBackgroundPattern,
+    WiderBackground,
+    NarrowerBackground,
+    ToggleSnapping,
+    PlaceImage,
+    Cancel, // Drop the pasted image, or else the selection
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Button {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+    Other(u16),
+}
+
+impl From<MouseButton> for Button {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => Button::Left,
+            MouseButton::Right => Button::Right,
+            MouseButton::Middle => Button::Middle,
+            MouseButton::Back => Button::Back,
+            MouseButton::Forward => Button::Forward,
+            MouseButton::Other(code) => Button::Other(code),
+        }
+    }
+}
+
+impl From<Button> for MouseButton {
+    fn from(button: Button) -> Self {
+        match button {
+            Button::Left => MouseButton::Left,
+            Button::Right => MouseButton::Right,
+            Button::Middle => MouseButton::Middle,
+            Button::Back => MouseButton::Back,
+            Button::Forward => MouseButton::Forward,
+            Button::Other(code) => MouseButton::Other(code),
+        }
+    }
+}
+
+// Where a recording starts off
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub zoom: f32,
+}
+
+impl Header {
+    pub fn new(width: u32, height: u32, view: View) -> Self {
+        Self {
+            version: VERSION,
+            width,
+            height,
+            offset_x: view.offset_x,
+            offset_y: view.offset_y,
+            zoom: view.zoom,
+        }
+    }
+
+    // Rejects what a window can't start off with, as session files do
+    fn check(&self) -> Result<(), String> {
+        let size = 1..=session::MAX_SIZE;
+        if !size.contains(&self.width) || !size.contains(&self.height) {
+            return Err(format!(
+                "invalid window size {}x{}",
+                self.width, self.height
+            ));
+        }
+        if !(self.zoom.is_finite() && self.zoom > 0.0) {
+            return Err("invalid zoom".to_string());
+        }
+        if !(self.offset_x.is_finite() && self.offset_y.is_finite()) {
+            return Err("invalid view offset".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            offset_x: self.offset_x,
+            offset_y: self.offset_y,
+            zoom: self.zoom,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TimedEvent {
+    time: u64, // Milliseconds since the start
+    #[serde(flatten)]
+    event: InputEvent,
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Empty,
+    NewerVersion(u32),
+    Invalid { line: usize, reason: String },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "{err}"),
+            RecordingError::Empty => write!(f, "the recording is empty"),
+            RecordingError::NewerVersion(version) => write!(
+                f,
+                "recording version {version} is newer than this build understands"
+            ),
+            RecordingError::Invalid { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl Error for RecordingError {}
+
+// Writes events as they happen. Lines go out one by one so a crash still
+// leaves everything up to it on disk
+pub struct Recorder {
+    file: LineWriter<File>,
+    start: Option<Instant>,
+    last_tool: Option<InputEvent>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: LineWriter::new(File::create(path)?),
+            start: None,
+            last_tool: None,
+        })
+    }
+
+    // Once the window exists, events before this are dropped
+    pub fn start(&mut self, header: &Header) -> io::Result<()> {
+        self.write_line(header)?;
+        self.start = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn record(&mut self, event: InputEvent) -> io::Result<()> {
+        let Some(start) = self.start else {
+            return Ok(());
+        };
+        if let InputEvent::Tool { .. } = event {
+            if self.last_tool == Some(event) {
+                return Ok(());
+            }
+            self.last_tool = Some(event);
+        }
+        let time = start.elapsed().as_millis() as u64;
+        self.write_line(&TimedEvent { time, event })
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> io::Result<()> {
+        let mut line = serde_json::to_string(value).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+// A loaded recording being played back, `speed` times as fast as it was
+// recorded
+pub struct Replay {
+    pub header: Header,
+    events: Vec<TimedEvent>,
+    next: usize,
+    start: Option<Instant>,
+    speed: f32,
+}
+
+impl Replay {
+    pub fn load(path: &Path, speed: f32) -> Result<Self, RecordingError> {
+        let text = fs::read_to_string(path).map_err(RecordingError::Io)?;
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let invalid = |index: usize, err: serde_json::Error| RecordingError::Invalid {
+            line: index + 1,
+            reason: err.to_string(),
+        };
+
+        let (index, line) = lines.next().ok_or(RecordingError::Empty)?;
+        let header: Header = serde_json::from_str(line).map_err(|err| invalid(index, err))?;
+        if header.version > VERSION {
+            return Err(RecordingError::NewerVersion(header.version));
+        }
+        if let Err(reason) = header.check() {
+            return Err(RecordingError::Invalid {
+                line: index + 1,
+                reason,
+            });
+        }
+        let events = lines
+            .map(|(index, line)| serde_json::from_str(line).map_err(|err| invalid(index, err)))
+            .collect::<Result<Vec<TimedEvent>, _>>()?;
+
+        Ok(Self {
+            header,
+            events,
+            next: 0,
+            start: None,
+            speed,
+        })
+    }
+
+    // The clock starts once the window is there to replay into
+    pub fn start(&mut self) {
+        self.start = Some(Instant::now());
+    }
+
+    // When the next event is due, `None` once everything has been played
+    pub fn deadline(&self) -> Option<Instant> {
+        let start = self.start?;
+        let event = self.events.get(self.next)?;
+        Some(start + Duration::from_millis(event.time).div_f32(self.speed))
+    }
+
+    pub fn next_due(&mut self, now: Instant) -> Option<InputEvent> {
+        if self.deadline()? > now {
+            return None;
+        }
+        self.next += 1;
+        Some(self.events[self.next - 1].event)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_play_back() {
+        let path = std::env::temp_dir().join(format!("drawonscreen-{}.rec", std::process::id()));
+        let events = [
+            InputEvent::Action {
+                action: Action::Undo,
+            },
+            InputEvent::Action {
+                action: Action::NextPage,
+            },
+        ];
+
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder
+            .start(&Header::new(640, 480, View::default()))
+            .unwrap();
+        for event in events {
+            recorder.record(event).unwrap();
+        }
+        drop(recorder);
+
+        let mut replay = Replay::load(&path, 1.0).unwrap();
+        fs::remove_file(&path).unwrap();
+        replay.start();
+        let now = Instant::now() + Duration::from_secs(1);
+        for event in events {
+            assert!(replay.next_due(now) == Some(event));
+        }
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let path =
+            std::env::temp_dir().join(format!("drawonscreen-{}-header.rec", std::process::id()));
+        for header in [
+            r#"{"version":1,"width":640,"height":480,"offset_x":0.0,"offset_y":0.0,"zoom":0.0}"#,
+            r#"{"version":1,"width":640,"height":480,"offset_x":0.0,"offset_y":0.0,"zoom":-2.0}"#,
+            r#"{"version":1,"width":0,"height":480,"offset_x":0.0,"offset_y":0.0,"zoom":1.0}"#,
+            r#"{"version":1,"width":640,"height":99999,"offset_x":0.0,"offset_y":0.0,"zoom":1.0}"#,
+            r#"{"version":1,"width":640,"height":480,"offset_x":1e39,"offset_y":0.0,"zoom":1.0}"#,
+        ] {
+            fs::write(&path, format!("{header}\n")).unwrap();
+            assert!(
+                matches!(
+                    Replay::load(&path, 1.0),
+                    Err(RecordingError::Invalid { line: 1, .. })
+                ),
+                "{header} was taken"
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
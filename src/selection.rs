@@ -0,0 +1,55 @@
+// Rectangular region picked with Shift + drag
+pub struct Selection {
+    pub start: (i32, i32),
+    pub end: (i32, i32),
+}
+
+impl Selection {
+    pub fn new(start: (i32, i32)) -> Self {
+        Self { start, end: start }
+    }
+
+    // The selected rectangle clamped to a `width` x `height` canvas as
+    // (x, y, width, height), or `None` if nothing of it is visible
+    pub fn rect(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let min_x = self.start.0.min(self.end.0).max(0);
+        let min_y = self.start.1.min(self.end.1).max(0);
+        let max_x = self.start.0.max(self.end.0).min(width as i32 - 1);
+        let max_y = self.start.1.max(self.end.1).min(height as i32 - 1);
+
+        if min_x > max_x || min_y > max_y {
+            return None;
+        }
+
+        Some((
+            min_x as u32,
+            min_y as u32,
+            (max_x - min_x + 1) as u32,
+            (max_y - min_y + 1) as u32,
+        ))
+    }
+
+    // Draw a dashed outline so the selection stays visible on any color
+    pub fn draw_outline(&self, target: &mut [u32], width: u32, height: u32) {
+        let Some((x, y, w, h)) = self.rect(width, height) else {
+            return;
+        };
+
+        let dash = |i: u32| {
+            if (i / 4).is_multiple_of(2) {
+                0xFFFFFFFF
+            } else {
+                0xFF000000
+            }
+        };
+
+        for i in 0..w {
+            target[(y * width + x + i) as usize] = dash(i);
+            target[((y + h - 1) * width + x + i) as usize] = dash(i);
+        }
+        for i in 0..h {
+            target[((y + i) * width + x) as usize] = dash(i);
+            target[((y + i) * width + x + w - 1) as usize] = dash(i);
+        }
+    }
+}
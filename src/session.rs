@@ -0,0 +1,474 @@
+// Native `.dos` session files
+//
+// Layout (all integers little-endian):
+//   magic            8 bytes  "DRAWONSC"
+//   version          u16
+//   width, height    u32, u32
+//   pointer color    u32
+//   draw radius      i32
+//   erase radius     i32
+//   palette          u8 count, then count * u32
+//   background       pattern u8, color u32, spacing f32, then a u8 that is 1
+//                    when an image follows (u32 length, PNG)
+//   page count       u32
+//   current page     u32
+//   per page:
+//     view           offset x f32, offset y f32, zoom f32
+//     elements       u32 count, then per element a u8 tag and
+//       0 stroke     color u32, radius f32, eraser u8, u32 count, count * (f32, f32)
+//       1 image      x f32, y f32, width f32, height f32, u32 length, PNG
+//
+// Files with an unknown or newer version are rejected rather than guessed at
+
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    background::{Background, Pattern},
+    document::{Element, Page, Stroke},
+    export,
+    image::Image,
+    view::View,
+};
+
+const MAGIC: &[u8; 8] = b"DRAWONSC";
+pub const VERSION: u16 = 1;
+// Larger than any monitor, small enough that a canvas buffer fits in memory
+pub const MAX_SIZE: u32 = 16384;
+
+pub struct Session {
+    pub width: u32,
+    pub height: u32,
+    pub pointer_color: u32,
+    pub draw_brush_radius: i32,
+    pub erase_brush_radius: i32,
+    pub palette: Vec<u32>,
+    pub background: Background,
+    pub pages: Vec<Page>,
+    pub current_page: usize,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    NotASession,
+    NewerVersion(u16),
+    UnknownVersion(u16),
+    Corrupt(String),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Io(err) => write!(f, "{err}"),
+            SessionError::NotASession => write!(f, "not a Draw On Screen session file"),
+            SessionError::NewerVersion(version) => write!(
+                f,
+                "session format version {version} was written by a newer release (this one reads up to {VERSION}), please upgrade"
+            ),
+            SessionError::UnknownVersion(version) => {
+                write!(f, "unknown session format version {version}")
+            }
+            SessionError::Corrupt(reason) => write!(f, "session file is corrupt: {reason}"),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SessionError::Corrupt("unexpected end of file".to_string())
+        } else {
+            SessionError::Io(err)
+        }
+    }
+}
+
+impl Session {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SessionError> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.pointer_color.to_le_bytes());
+        bytes.extend_from_slice(&self.draw_brush_radius.to_le_bytes());
+        bytes.extend_from_slice(&self.erase_brush_radius.to_le_bytes());
+
+        let palette_len = u8::try_from(self.palette.len())
+            .map_err(|_| SessionError::Corrupt("palette is too large".to_string()))?;
+        bytes.push(palette_len);
+        for color in &self.palette {
+            bytes.extend_from_slice(&color.to_le_bytes());
+        }
+
+        bytes.push(self.background.pattern.to_u8());
+        bytes.extend_from_slice(&self.background.color.to_le_bytes());
+        bytes.extend_from_slice(&self.background.spacing.to_le_bytes());
+        match &self.background.image {
+            Some(image) => {
+                bytes.push(1);
+                write_png(&mut bytes, image.width, image.height, &image.pixels)?;
+            }
+            None => bytes.push(0),
+        }
+
+        bytes.extend_from_slice(&(self.pages.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.current_page as u32).to_le_bytes());
+        for page in &self.pages {
+            bytes.extend_from_slice(&page.view.offset_x.to_le_bytes());
+            bytes.extend_from_slice(&page.view.offset_y.to_le_bytes());
+            bytes.extend_from_slice(&page.view.zoom.to_le_bytes());
+            write_elements(&mut bytes, &page.elements)?;
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, SessionError> {
+        let mut magic = [0; 8];
+        bytes
+            .read_exact(&mut magic)
+            .map_err(|_| SessionError::NotASession)?;
+        if &magic != MAGIC {
+            return Err(SessionError::NotASession);
+        }
+
+        let version = u16::from_le_bytes(read_array(&mut bytes)?);
+        if version > VERSION {
+            return Err(SessionError::NewerVersion(version));
+        }
+        // Versions count from 1, anything else was never written by a release
+        if version != VERSION {
+            return Err(SessionError::UnknownVersion(version));
+        }
+
+        let width = u32::from_le_bytes(read_array(&mut bytes)?);
+        let height = u32::from_le_bytes(read_array(&mut bytes)?);
+        if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+            return Err(SessionError::Corrupt(format!(
+                "invalid canvas size {width}x{height}"
+            )));
+        }
+        let pointer_color = u32::from_le_bytes(read_array(&mut bytes)?);
+        let draw_brush_radius = i32::from_le_bytes(read_array(&mut bytes)?);
+        let erase_brush_radius = i32::from_le_bytes(read_array(&mut bytes)?);
+
+        let [palette_len] = read_array(&mut bytes)?;
+        let palette = (0..palette_len)
+            .map(|_| read_array(&mut bytes).map(u32::from_le_bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let [pattern] = read_array(&mut bytes)?;
+        let mut background = Background {
+            pattern: Pattern::from_u8(pattern).ok_or_else(|| {
+                SessionError::Corrupt(format!("unknown background pattern {pattern}"))
+            })?,
+            color: u32::from_le_bytes(read_array(&mut bytes)?),
+            spacing: f32::from_le_bytes(read_array(&mut bytes)?),
+            image: None,
+        };
+        if !(background.spacing.is_finite() && background.spacing > 0.0) {
+            return Err(SessionError::Corrupt(
+                "invalid background spacing".to_string(),
+            ));
+        }
+        let [has_image] = read_array(&mut bytes)?;
+        if has_image != 0 {
+            background.image = Some(Arc::new(read_png(&mut bytes)?));
+        }
+
+        let page_count = u32::from_le_bytes(read_array(&mut bytes)?);
+        let current_page = u32::from_le_bytes(read_array(&mut bytes)?) as usize;
+        if page_count == 0 || current_page >= page_count as usize {
+            return Err(SessionError::Corrupt("invalid page list".to_string()));
+        }
+
+        let mut pages = Vec::new();
+        for _ in 0..page_count {
+            let view = View {
+                offset_x: f32::from_le_bytes(read_array(&mut bytes)?),
+                offset_y: f32::from_le_bytes(read_array(&mut bytes)?),
+                zoom: f32::from_le_bytes(read_array(&mut bytes)?),
+            };
+            if !(view.zoom.is_finite() && view.zoom > 0.0) {
+                return Err(SessionError::Corrupt("invalid zoom".to_string()));
+            }
+
+            pages.push(Page {
+                elements: read_elements(&mut bytes)?,
+                view,
+                ..Page::default()
+            });
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pointer_color,
+            draw_brush_radius,
+            erase_brush_radius,
+            palette,
+            background,
+            pages,
+            current_page,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SessionError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    // Write to a temporary file first so a crash mid-save never leaves a
+    // half-written session behind
+    pub fn save(&self, path: &Path) -> Result<(), SessionError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("dos.tmp");
+        fs::write(&temporary, self.to_bytes()?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+fn read_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], SessionError> {
+    let mut array = [0; N];
+    bytes.read_exact(&mut array)?;
+    Ok(array)
+}
+
+fn write_elements(bytes: &mut Vec<u8>, elements: &[Element]) -> Result<(), SessionError> {
+    bytes.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+    for element in elements {
+        match element {
+            Element::Stroke(stroke) => {
+                bytes.push(0);
+                bytes.extend_from_slice(&stroke.color.to_le_bytes());
+                bytes.extend_from_slice(&stroke.radius.to_le_bytes());
+                bytes.push(stroke.is_eraser as u8);
+                bytes.extend_from_slice(&(stroke.points.len() as u32).to_le_bytes());
+                for (x, y) in &stroke.points {
+                    bytes.extend_from_slice(&x.to_le_bytes());
+                    bytes.extend_from_slice(&y.to_le_bytes());
+                }
+            }
+            Element::Image {
+                x,
+                y,
+                width,
+                height,
+                image,
+            } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&x.to_le_bytes());
+                bytes.extend_from_slice(&y.to_le_bytes());
+                bytes.extend_from_slice(&width.to_le_bytes());
+                bytes.extend_from_slice(&height.to_le_bytes());
+                write_png(bytes, image.width, image.height, &image.pixels)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_png(
+    bytes: &mut Vec<u8>,
+    width: u32,
+    height: u32,
+    pixels: &[u32],
+) -> Result<(), SessionError> {
+    // Rasters are stored as PNGs, which keeps mostly empty canvases tiny
+    let png = export::encode_png(width, height, &export::to_rgba(pixels))
+        .map_err(|err| SessionError::Io(io::Error::other(err)))?;
+    bytes.extend_from_slice(&(png.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&png);
+    Ok(())
+}
+
+fn read_png(bytes: &mut &[u8]) -> Result<Image, SessionError> {
+    let len = u32::from_le_bytes(read_array(bytes)?) as usize;
+    if len > bytes.len() {
+        return Err(SessionError::Corrupt("raster is truncated".to_string()));
+    }
+    let (png, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Image::decode_png(png).map_err(|err| SessionError::Corrupt(err.to_string()))
+}
+
+fn read_elements(bytes: &mut &[u8]) -> Result<Vec<Element>, SessionError> {
+    let count = u32::from_le_bytes(read_array(bytes)?);
+    let mut elements = Vec::new();
+
+    for _ in 0..count {
+        let [tag] = read_array(bytes)?;
+        let element = match tag {
+            0 => {
+                let color = u32::from_le_bytes(read_array(bytes)?);
+                let radius = f32::from_le_bytes(read_array(bytes)?);
+                let [is_eraser] = read_array(bytes)?;
+                let point_count = u32::from_le_bytes(read_array(bytes)?);
+                let points = (0..point_count)
+                    .map(|_| {
+                        Ok((
+                            f32::from_le_bytes(read_array(bytes)?),
+                            f32::from_le_bytes(read_array(bytes)?),
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, SessionError>>()?;
+
+                Element::Stroke(Stroke {
+                    color,
+                    radius,
+                    is_eraser: is_eraser != 0,
+                    points,
+                    id: None,
+                })
+            }
+            1 => {
+                let x = f32::from_le_bytes(read_array(bytes)?);
+                let y = f32::from_le_bytes(read_array(bytes)?);
+                let width = f32::from_le_bytes(read_array(bytes)?);
+                let height = f32::from_le_bytes(read_array(bytes)?);
+                let image = read_png(bytes)?;
+
+                Element::Image {
+                    x,
+                    y,
+                    width,
+                    height,
+                    image: Arc::new(image),
+                }
+            }
+            tag => return Err(SessionError::Corrupt(format!("unknown element type {tag}"))),
+        };
+        elements.push(element);
+    }
+
+    Ok(elements)
+}
+
+// $XDG_STATE_HOME/drawonscreen/autosave.dos (~/.local/state by default)
+pub fn autosave_path() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+        })?;
+
+    Some(state_home.join("drawonscreen").join("autosave.dos"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            width: 64,
+            height: 48,
+            pointer_color: 0x00ef4444,
+            draw_brush_radius: 2,
+            erase_brush_radius: 7,
+            palette: vec![0x00ef4444, 0x0022c55e],
+            background: Background::default(),
+            pages: vec![Page {
+                elements: vec![Element::Stroke(Stroke {
+                    color: 0x003b82f6,
+                    radius: 1.5,
+                    is_eraser: false,
+                    points: vec![(1.0, 2.0), (10.5, 20.25)],
+                    id: None,
+                })],
+                view: View {
+                    offset_x: 3.0,
+                    offset_y: -4.0,
+                    zoom: 2.0,
+                },
+                ..Page::default()
+            }],
+            current_page: 0,
+        }
+    }
+
+    fn with_version(version: u16) -> Vec<u8> {
+        let mut bytes = session().to_bytes().unwrap();
+        bytes[8..10].copy_from_slice(&version.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let loaded = Session::from_bytes(&session().to_bytes().unwrap()).unwrap();
+        assert_eq!((loaded.width, loaded.height), (64, 48));
+        assert_eq!(loaded.draw_brush_radius, 2);
+        assert_eq!(loaded.erase_brush_radius, 7);
+        assert_eq!(loaded.palette, [0x00ef4444, 0x0022c55e]);
+        assert_eq!(loaded.pages[0].view.zoom, 2.0);
+        let [Element::Stroke(stroke)] = &loaded.pages[0].elements[..] else {
+            panic!("expected one stroke");
+        };
+        assert_eq!(stroke.points, [(1.0, 2.0), (10.5, 20.25)]);
+    }
+
+    #[test]
+    fn rejects_version_zero() {
+        assert!(matches!(
+            Session::from_bytes(&with_version(0)),
+            Err(SessionError::UnknownVersion(0))
+        ));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        assert!(matches!(
+            Session::from_bytes(&with_version(VERSION + 1)),
+            Err(SessionError::NewerVersion(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            Session::from_bytes(b"\x89PNG\r\n\x1a\n"),
+            Err(SessionError::NotASession)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_size() {
+        for (width, height) in [(0, 48u32), (64, 0), (MAX_SIZE + 1, 48)] {
+            let mut bytes = session().to_bytes().unwrap();
+            bytes[10..14].copy_from_slice(&width.to_le_bytes());
+            bytes[14..18].copy_from_slice(&height.to_le_bytes());
+            assert!(matches!(
+                Session::from_bytes(&bytes),
+                Err(SessionError::Corrupt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_zoom() {
+        let mut bytes = session().to_bytes().unwrap();
+        // The zoom of the only page sits right before its elements: the count,
+        // then one stroke with two points
+        let elements = 4 + 1 + 4 + 4 + 1 + 4 + 2 * 8;
+        let zoom = bytes.len() - elements - 4;
+        bytes[zoom..zoom + 4].copy_from_slice(&0.0f32.to_le_bytes());
+        assert!(matches!(
+            Session::from_bytes(&bytes),
+            Err(SessionError::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,819 @@
+// Drawing on one canvas from several instances. One instance hosts, the others
+// join it over TCP, and everything goes through the host, which relays it to
+// the rest. Each line on a connection is one JSON message:
+//
+//   join   -> {"type":"hello","name":"ana"}
+//   host   -> {"type":"welcome","peer":2,"peers":[...],"strokes":[...],"background":{...}}
+//   either -> {"type":"begin","stroke":{"id":{"peer":2,"serial":0},...}}
+//   either -> {"type":"extend","id":{"peer":2,"serial":0},"points":[[10.0,4.5]]}
+//   either -> {"type":"remove","ids":[{"peer":2,"serial":0}]}
+//   either -> {"type":"background","pattern":"grid","color":16777215,"spacing":40.0}
+//
+// Strokes carry the id of the peer that drew them, so undo and clear only ever
+// touch one's own strokes whatever the others did in between. A joining
+// instance gets the host's strokes and background in place of its own page.
+// Pasted images and background pictures stay with the instance that has them:
+// pasting is turned down on a shared canvas
+//
+// A read-only host broadcasts to viewers: it says so in the welcome and drops
+// anything they send but the hello
+//
+// Every connection has a thread of its own writing to it, so a peer that stops
+// reading never holds up drawing or the others. Once its queue is full it gets
+// dropped
+//
+// Nothing that arrives is trusted: overlong lines drop the connection, and
+// strokes and backgrounds the UI couldn't have made are ignored
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, SyncSender},
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use winit::event_loop::EventLoopProxy;
+
+use crate::{
+    background::{self, Background, Pattern},
+    control::UserEvent,
+    document::{Stroke, StrokeId},
+    view,
+};
+
+pub const HOST_PEER: u32 = 0;
+
+// A peer that stops reading gets dropped rather than holding everyone up
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+// Messages waiting for a connection, a stroke's worth of points many times over
+const QUEUE_LENGTH: usize = 4096;
+// Longest line a peer may send, far more than any one message needs
+const MAX_LINE: u64 = 1 << 20;
+// The welcome carries the whole page so far
+const MAX_WELCOME: u64 = 1 << 28;
+
+// Stroke radii the brushes can make, the thinnest pen zoomed all the way in to
+// the widest eraser zoomed all the way out
+const MIN_RADIUS: f32 = 1.0 / view::MAX_ZOOM;
+const MAX_RADIUS: f32 = 50.0 / view::MIN_ZOOM;
+// Further out than anyone pans to draw, close enough to rasterize
+const MAX_COORDINATE: f32 = 1e6;
+
+// Hands what a connection receives to the event loop
+type Deliver = Arc<dyn Fn(PeerEvent) + Send + Sync>;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Hello {
+        name: String,
+    },
+    Welcome(Welcome),
+    Joined(Peer),
+    Left {
+        peer: u32,
+    },
+    Begin {
+        stroke: SharedStroke,
+    },
+    Extend {
+        id: StrokeId,
+        points: Vec<(f32, f32)>,
+    },
+    Remove {
+        ids: Vec<StrokeId>,
+    },
+    Background(SharedBackground),
+}
+
+impl Message {
+    // Whether the numbers in it are ones the UI could have sent
+    fn is_valid(&self) -> bool {
+        match self {
+            Message::Welcome(welcome) => {
+                welcome.strokes.iter().all(SharedStroke::is_valid)
+                    && welcome
+                        .background
+                        .is_none_or(|background| background.is_valid())
+            }
+            Message::Begin { stroke } => stroke.is_valid(),
+            Message::Extend { points, .. } => points.iter().all(|&point| is_valid_point(point)),
+            Message::Background(background) => background.is_valid(),
+            _ => true,
+        }
+    }
+}
+
+// The host's answer to `hello`: the id the new peer draws under, who else is
+// there, the document so far and whether the new peer may draw at all
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Welcome {
+    pub peer: u32,
+    pub peers: Vec<Peer>,
+    pub strokes: Vec<SharedStroke>,
+    pub background: Option<SharedBackground>,
+    pub read_only: bool,
+}
+
+// What a joining instance takes over from the host
+pub struct SharedPage {
+    pub strokes: Vec<SharedStroke>,
+    pub background: Option<SharedBackground>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub peer: u32,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SharedStroke {
+    pub id: StrokeId,
+    pub color: u32,
+    pub radius: f32,
+    pub is_eraser: bool,
+    pub points: Vec<(f32, f32)>,
+}
+
+impl SharedStroke {
+    // `None` for strokes that were never shared
+    pub fn from_stroke(stroke: &Stroke) -> Option<Self> {
+        Some(Self {
+            id: stroke.id?,
+            color: stroke.color,
+            radius: stroke.radius,
+            is_eraser: stroke.is_eraser,
+            points: stroke.points.clone(),
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        (MIN_RADIUS..=MAX_RADIUS).contains(&self.radius)
+            && self.points.iter().all(|&point| is_valid_point(point))
+    }
+
+    pub fn into_stroke(self) -> Stroke {
+        Stroke {
+            color: self.color,
+            radius: self.radius,
+            is_eraser: self.is_eraser,
+            points: self.points,
+            id: Some(self.id),
+        }
+    }
+}
+
+// The background without its picture
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SharedBackground {
+    pub pattern: Pattern,
+    pub color: u32,
+    pub spacing: f32,
+}
+
+impl SharedBackground {
+    pub fn from_background(background: &Background) -> Self {
+        Self {
+            pattern: background.pattern,
+            color: background.color,
+            spacing: background.spacing,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (background::MIN_SPACING..=background::MAX_SPACING).contains(&self.spacing)
+    }
+
+    pub fn apply_to(self, background: &mut Background) {
+        background.pattern = self.pattern;
+        background.color = self.color;
+        background.spacing = self.spacing;
+    }
+}
+
+// What a connection hands to the event loop
+pub enum PeerEvent {
+    Message(Message),
+    Disconnected, // From the host, the canvas is no longer shared
+}
+
+// This instance's side of a shared canvas
+pub struct Sharing {
+    connection: Connection,
+    pub peer: u32,
+    pub names: HashMap<u32, String>,
+    pub redo: Vec<Stroke>, // Own strokes taken back by undo
+    pub read_only: bool,   // Only watching what the others draw
+    next_serial: u32,
+}
+
+impl Sharing {
+    pub fn new(connection: Connection, peer: u32, peers: Vec<Peer>, read_only: bool) -> Self {
+        Self {
+            connection,
+            peer,
+            names: peers
+                .into_iter()
+                .map(|peer| (peer.peer, peer.name))
+                .collect(),
+            redo: Vec::new(),
+            read_only,
+            next_serial: 0,
+        }
+    }
+
+    pub fn next_id(&mut self) -> StrokeId {
+        let id = StrokeId {
+            peer: self.peer,
+            serial: self.next_serial,
+        };
+        self.next_serial += 1;
+        id
+    }
+
+    pub fn send(&self, message: &Message) {
+        if let Err(err) = self.connection.send(message) {
+            eprintln!("Failed to send to the shared canvas: {err}");
+        }
+    }
+}
+
+pub enum Connection {
+    Host(Arc<Mutex<Hub>>),
+    Client(Outbox),
+}
+
+impl Connection {
+    fn send(&self, message: &Message) -> io::Result<()> {
+        match self {
+            Connection::Host(hub) => {
+                lock(hub).relay(HOST_PEER, message);
+                Ok(())
+            }
+            Connection::Client(outbox) => outbox.send(&to_line(message)?),
+        }
+    }
+}
+
+// The writing side of a connection. Lines are queued for its thread, and the
+// connection is shut down, ending the reading side too, as soon as one can't
+// be written or queued
+pub struct Outbox {
+    stream: TcpStream,
+    queue: SyncSender<String>,
+}
+
+impl Outbox {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        let (queue, lines) = mpsc::sync_channel::<String>(QUEUE_LENGTH);
+        thread::spawn(move || {
+            for line in lines {
+                if writer.write_all(line.as_bytes()).is_err() {
+                    let _ = writer.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+        Ok(Self { stream, queue })
+    }
+
+    fn send(&self, line: &str) -> io::Result<()> {
+        self.queue.try_send(line.to_string()).map_err(|_| {
+            let _ = self.stream.shutdown(Shutdown::Both);
+            io::Error::other("the connection stalled or closed")
+        })
+    }
+}
+
+// The host's view of everyone: a copy of the shared page for late joiners and
+// a connection to every other peer
+pub struct Hub {
+    peers: Vec<Peer>,
+    strokes: Vec<SharedStroke>,
+    background: Option<SharedBackground>,
+    clients: Vec<(u32, Outbox)>,
+    next_peer: u32,
+    read_only: bool, // Nobody draws but the host
+}
+
+impl Hub {
+    // Keep the copy of the page up to date and pass the message on to everyone
+    // but its sender
+    fn relay(&mut self, from: u32, message: &Message) {
+        match message {
+            Message::Begin { stroke } => self.strokes.push(stroke.clone()),
+            Message::Extend { id, points } => {
+                if let Some(stroke) = self.strokes.iter_mut().find(|stroke| stroke.id == *id) {
+                    stroke.points.extend_from_slice(points);
+                }
+            }
+            Message::Remove { ids } => self.strokes.retain(|stroke| !ids.contains(&stroke.id)),
+            Message::Background(background) => self.background = Some(*background),
+            _ => {}
+        }
+        let Ok(line) = to_line(message) else {
+            return;
+        };
+        self.clients
+            .retain(|(peer, outbox)| *peer == from || outbox.send(&line).is_ok());
+    }
+}
+
+// Start hosting on `listener` with this instance as the first peer. Its own
+// strokes reach the others once it sends them. With `read_only` the others
+// only watch
+pub fn host(
+    listener: TcpListener,
+    name: String,
+    read_only: bool,
+    proxy: EventLoopProxy<UserEvent>,
+) -> Sharing {
+    host_with(listener, name, read_only, to_event_loop(proxy))
+}
+
+fn host_with(listener: TcpListener, name: String, read_only: bool, deliver: Deliver) -> Sharing {
+    let me = Peer {
+        peer: HOST_PEER,
+        name,
+    };
+    let hub = Arc::new(Mutex::new(Hub {
+        peers: vec![me.clone()],
+        strokes: Vec::new(),
+        background: None,
+        clients: Vec::new(),
+        next_peer: HOST_PEER + 1,
+        read_only,
+    }));
+
+    {
+        let hub = hub.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let hub = hub.clone();
+                let deliver = deliver.clone();
+                thread::spawn(move || serve_peer(stream, &hub, &*deliver));
+            }
+        });
+    }
+
+    Sharing::new(Connection::Host(hub), HOST_PEER, vec![me], false)
+}
+
+fn serve_peer(stream: TcpStream, hub: &Mutex<Hub>, deliver: &dyn Fn(PeerEvent)) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(reader);
+    let Some(Ok(Message::Hello { name })) =
+        read_line(&mut reader, MAX_LINE).map(|line| serde_json::from_str(&line))
+    else {
+        return;
+    };
+    let Ok(outbox) = Outbox::new(stream) else {
+        return;
+    };
+
+    // Joining happens under the lock, so nothing is missed between the welcome
+    // and the first relayed message
+    let joined = {
+        let mut hub = lock(hub);
+        let peer = hub.next_peer;
+        hub.next_peer += 1;
+        let welcome = Message::Welcome(Welcome {
+            peer,
+            peers: hub.peers.clone(),
+            strokes: hub.strokes.clone(),
+            background: hub.background,
+            read_only: hub.read_only,
+        });
+        if to_line(&welcome)
+            .and_then(|line| outbox.send(&line))
+            .is_err()
+        {
+            return;
+        }
+        let joined = Peer { peer, name };
+        hub.peers.push(joined.clone());
+        hub.relay(peer, &Message::Joined(joined.clone()));
+        hub.clients.push((peer, outbox));
+        joined
+    };
+    let peer = joined.peer;
+    let read_only = lock(hub).read_only;
+    deliver(PeerEvent::Message(Message::Joined(joined)));
+
+    while let Some(line) = read_line(&mut reader, MAX_LINE) {
+        let Ok(message) = serde_json::from_str::<Message>(&line) else {
+            continue;
+        };
+        // Peers only get to draw and take back their own strokes
+        let message = match message {
+            _ if read_only => continue,
+            message if !message.is_valid() => continue,
+            Message::Begin { stroke } if stroke.id.peer == peer => Message::Begin { stroke },
+            Message::Extend { id, points } if id.peer == peer => Message::Extend { id, points },
+            Message::Remove { ids } => Message::Remove {
+                ids: ids.into_iter().filter(|id| id.peer == peer).collect(),
+            },
+            Message::Background(background) => Message::Background(background),
+            _ => continue,
+        };
+        lock(hub).relay(peer, &message);
+        deliver(PeerEvent::Message(message));
+    }
+
+    let left = Message::Left { peer };
+    {
+        let mut hub = lock(hub);
+        hub.clients.retain(|(client, _)| *client != peer);
+        hub.peers.retain(|other| other.peer != peer);
+        hub.relay(peer, &left);
+    }
+    deliver(PeerEvent::Message(left));
+}
+
+// Join the canvas hosted at `address` (host:port), only to watch with
+// `read_only` or when the host says so. Returns once the host has sent the
+// page
+pub fn join(
+    address: &str,
+    name: String,
+    read_only: bool,
+    proxy: EventLoopProxy<UserEvent>,
+) -> io::Result<(Sharing, SharedPage)> {
+    join_with(address, name, read_only, to_event_loop(proxy))
+}
+
+fn join_with(
+    address: &str,
+    name: String,
+    read_only: bool,
+    deliver: Deliver,
+) -> io::Result<(Sharing, SharedPage)> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(to_line(&Message::Hello { name })?.as_bytes())?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let welcome = read_line(&mut reader, MAX_WELCOME)
+        .and_then(|line| serde_json::from_str::<Message>(&line).ok())
+        .filter(Message::is_valid);
+    let Some(Message::Welcome(welcome)) = welcome else {
+        return Err(io::Error::other(format!(
+            "{address} is not a shared canvas"
+        )));
+    };
+
+    thread::spawn(move || {
+        while let Some(line) = read_line(&mut reader, MAX_LINE) {
+            if let Ok(message) = serde_json::from_str::<Message>(&line)
+                && message.is_valid()
+            {
+                deliver(PeerEvent::Message(message));
+            }
+        }
+        deliver(PeerEvent::Disconnected);
+    });
+
+    let read_only = read_only || welcome.read_only;
+    let sharing = Sharing::new(
+        Connection::Client(Outbox::new(stream)?),
+        welcome.peer,
+        welcome.peers,
+        read_only,
+    );
+    let page = SharedPage {
+        strokes: welcome.strokes,
+        background: welcome.background,
+    };
+    Ok((sharing, page))
+}
+
+// The next line from a connection, `None` once it ends, fails or sends a line
+// longer than `limit`
+fn read_line(reader: &mut impl BufRead, limit: u64) -> Option<String> {
+    let mut line = String::new();
+    let read = reader.by_ref().take(limit).read_line(&mut line).ok()?;
+    if read == 0 || (read as u64 == limit && !line.ends_with('\n')) {
+        return None;
+    }
+    Some(line)
+}
+
+fn is_valid_point((x, y): (f32, f32)) -> bool {
+    x.abs() <= MAX_COORDINATE && y.abs() <= MAX_COORDINATE
+}
+
+fn to_event_loop(proxy: EventLoopProxy<UserEvent>) -> Deliver {
+    Arc::new(move |event| {
+        let _ = proxy.send_event(UserEvent::Peer(event));
+    })
+}
+
+fn to_line(message: &Message) -> io::Result<String> {
+    let mut line = serde_json::to_string(message).map_err(io::Error::other)?;
+    line.push('\n');
+    Ok(line)
+}
+
+// A peer thread that panicked can't leave the hub in a state worth refusing
+fn lock(hub: &Mutex<Hub>) -> std::sync::MutexGuard<'_, Hub> {
+    hub.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::mpsc::{Receiver, channel},
+    };
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Events as the event loop would get them
+    fn events() -> (Deliver, Receiver<PeerEvent>) {
+        let (sender, receiver) = channel();
+        let deliver: Deliver = Arc::new(move |event| {
+            let _ = sender.send(event);
+        });
+        (deliver, receiver)
+    }
+
+    fn start_host(read_only: bool) -> (Sharing, SocketAddr, Receiver<PeerEvent>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (deliver, events) = events();
+        let host = host_with(listener, "host".to_string(), read_only, deliver);
+        (host, address, events)
+    }
+
+    fn start_peer(
+        address: SocketAddr,
+        name: &str,
+        read_only: bool,
+    ) -> (Sharing, SharedPage, Receiver<PeerEvent>) {
+        let (deliver, events) = events();
+        let (sharing, page) =
+            join_with(&address.to_string(), name.to_string(), read_only, deliver).unwrap();
+        (sharing, page, events)
+    }
+
+    // The first message `pick` takes, skipping the others
+    fn wait_for<T>(events: &Receiver<PeerEvent>, pick: impl Fn(Message) -> Option<T>) -> T {
+        loop {
+            match events.recv_timeout(TIMEOUT) {
+                Ok(PeerEvent::Message(message)) => {
+                    if let Some(picked) = pick(message) {
+                        return picked;
+                    }
+                }
+                Ok(PeerEvent::Disconnected) => panic!("disconnected"),
+                Err(err) => panic!("nothing came: {err}"),
+            }
+        }
+    }
+
+    fn stroke(sharing: &mut Sharing, points: &[(f32, f32)]) -> SharedStroke {
+        SharedStroke {
+            id: sharing.next_id(),
+            color: 0xef4444,
+            radius: 2.0,
+            is_eraser: false,
+            points: points.to_vec(),
+        }
+    }
+
+    fn background() -> SharedBackground {
+        SharedBackground {
+            pattern: Pattern::Grid,
+            color: 0xffffff,
+            spacing: 25.0,
+        }
+    }
+
+    #[test]
+    fn peers_share_a_canvas() {
+        let (host, address, host_events) = start_host(false);
+        let (mut ana, _, ana_events) = start_peer(address, "ana", false);
+        let (mut bo, _, bo_events) = start_peer(address, "bo", false);
+        assert_eq!(bo.names.get(&ana.peer).map(String::as_str), Some("ana"));
+        wait_for(&ana_events, |message| match message {
+            Message::Joined(peer) => (peer.name == "bo").then_some(()),
+            _ => None,
+        });
+
+        // Strokes reach everyone under the id of whoever drew them
+        let first = stroke(&mut ana, &[(10.0, 10.0)]);
+        ana.send(&Message::Begin {
+            stroke: first.clone(),
+        });
+        ana.send(&Message::Extend {
+            id: first.id,
+            points: vec![(20.0, 15.0)],
+        });
+        for events in [&bo_events, &host_events] {
+            let begun = wait_for(events, |message| match message {
+                Message::Begin { stroke } => Some(stroke),
+                _ => None,
+            });
+            assert!(begun.id == first.id && begun.id.peer == ana.peer);
+            let (id, points) = wait_for(events, |message| match message {
+                Message::Extend { id, points } => Some((id, points)),
+                _ => None,
+            });
+            assert!(id == first.id && points == [(20.0, 15.0)]);
+        }
+
+        // Undo only takes back one's own strokes
+        let own = stroke(&mut bo, &[(50.0, 50.0)]);
+        bo.send(&Message::Begin {
+            stroke: own.clone(),
+        });
+        bo.send(&Message::Remove {
+            ids: vec![first.id, own.id],
+        });
+        let removed = wait_for(&ana_events, |message| match message {
+            Message::Remove { ids } => Some(ids),
+            _ => None,
+        });
+        assert!(removed == [own.id]);
+
+        host.send(&Message::Background(background()));
+        let changed = wait_for(&bo_events, |message| match message {
+            Message::Background(background) => Some(background),
+            _ => None,
+        });
+        assert!(changed.pattern == Pattern::Grid && changed.spacing == 25.0);
+
+        // Anyone joining late gets the page as it is now
+        let (_cy, page, _) = start_peer(address, "cy", false);
+        assert_eq!(page.strokes.len(), 1);
+        assert!(page.strokes[0].id == first.id);
+        assert_eq!(page.strokes[0].points, [(10.0, 10.0), (20.0, 15.0)]);
+        assert!(page.background.is_some_and(|page| page.color == 0xffffff));
+    }
+
+    #[test]
+    fn viewers_only_watch() {
+        let (mut host, address, host_events) = start_host(true);
+        let drawn = stroke(&mut host, &[(5.0, 5.0)]);
+        host.send(&Message::Begin {
+            stroke: drawn.clone(),
+        });
+        host.send(&Message::Background(background()));
+
+        // Joining with --join still only gets to watch, and sees the page so far
+        let (viewer, page, viewer_events) = start_peer(address, "ana", false);
+        assert!(viewer.read_only);
+        assert!(page.strokes.len() == 1 && page.strokes[0].id == drawn.id);
+        assert!(
+            page.background
+                .is_some_and(|page| page.pattern == Pattern::Grid)
+        );
+
+        let more = stroke(&mut host, &[(8.0, 8.0)]);
+        host.send(&Message::Begin {
+            stroke: more.clone(),
+        });
+        let begun = wait_for(&viewer_events, |message| match message {
+            Message::Begin { stroke } => Some(stroke.id),
+            _ => None,
+        });
+        assert!(begun == more.id);
+        host.send(&Message::Background(SharedBackground {
+            color: 0x1e1e1e,
+            ..background()
+        }));
+        let changed = wait_for(&viewer_events, |message| match message {
+            Message::Background(background) => Some(background.color),
+            _ => None,
+        });
+        assert_eq!(changed, 0x1e1e1e);
+
+        // Whatever a viewer sends is dropped, up to it leaving
+        let mut sneaky = TcpStream::connect(address).unwrap();
+        sneaky
+            .write_all(
+                to_line(&Message::Hello {
+                    name: "bo".to_string(),
+                })
+                .unwrap()
+                .as_bytes(),
+            )
+            .unwrap();
+        let mut welcome = String::new();
+        BufReader::new(sneaky.try_clone().unwrap())
+            .read_line(&mut welcome)
+            .unwrap();
+        let Ok(Message::Welcome(Welcome { peer, .. })) = serde_json::from_str(&welcome) else {
+            panic!("expected a welcome");
+        };
+        let forged = SharedStroke {
+            id: StrokeId { peer, serial: 0 },
+            ..drawn
+        };
+        for message in [
+            Message::Begin { stroke: forged },
+            Message::Background(background()),
+        ] {
+            sneaky
+                .write_all(to_line(&message).unwrap().as_bytes())
+                .unwrap();
+        }
+        sneaky.shutdown(Shutdown::Write).unwrap();
+        let left = wait_for(&host_events, |message| match message {
+            Message::Left { peer } => Some(peer),
+            Message::Begin { .. } | Message::Background(_) => panic!("a viewer drew"),
+            _ => None,
+        });
+        assert_eq!(left, peer);
+    }
+
+    #[test]
+    fn stalled_peers_get_dropped() {
+        let (mut host, address, host_events) = start_host(false);
+        // Says hello, then never reads
+        let mut stalled = TcpStream::connect(address).unwrap();
+        stalled
+            .write_all(
+                to_line(&Message::Hello {
+                    name: "zed".to_string(),
+                })
+                .unwrap()
+                .as_bytes(),
+            )
+            .unwrap();
+        let peer = wait_for(&host_events, |message| match message {
+            Message::Joined(peer) => Some(peer.peer),
+            _ => None,
+        });
+
+        // Far more than the socket buffers and the queue hold together
+        let points = vec![(1.0, 2.0); 200];
+        for _ in 0..2 * QUEUE_LENGTH {
+            let stroke = stroke(&mut host, &points);
+            host.send(&Message::Begin { stroke });
+        }
+        let left = wait_for(&host_events, |message| match message {
+            Message::Left { peer } => Some(peer),
+            _ => None,
+        });
+        assert_eq!(left, peer);
+    }
+
+    #[test]
+    fn nonsense_gets_ignored() {
+        let (_host, address, host_events) = start_host(false);
+        let mut rogue = TcpStream::connect(address).unwrap();
+        rogue
+            .write_all(b"{\"type\":\"hello\",\"name\":\"zed\"}\n")
+            .unwrap();
+        let peer = wait_for(&host_events, |message| match message {
+            Message::Joined(peer) => Some(peer.peer),
+            _ => None,
+        });
+
+        // Radii, points and spacings the UI can't make, then one it can
+        let stroke = |radius: &str, point: &str| {
+            format!(
+                "{{\"type\":\"begin\",\"stroke\":{{\"id\":{{\"peer\":{peer},\"serial\":0}},\
+                 \"color\":0,\"radius\":{radius},\"is_eraser\":false,\"points\":[{point}]}}}}\n"
+            )
+        };
+        for line in [
+            stroke("1e30", "[1.0,2.0]"),
+            stroke("2.0", "[1e39,2.0]"),
+            format!(
+                "{{\"type\":\"extend\",\"id\":{{\"peer\":{peer},\"serial\":0}},\"points\":[[-1e39,0.0]]}}\n"
+            ),
+            "{\"type\":\"background\",\"pattern\":\"grid\",\"color\":0,\"spacing\":1e9}\n"
+                .to_string(),
+            stroke("2.0", "[1.0,2.0]"),
+        ] {
+            rogue.write_all(line.as_bytes()).unwrap();
+        }
+        let radius = wait_for(&host_events, |message| match message {
+            Message::Begin { stroke } => Some(stroke.radius),
+            Message::Extend { .. } | Message::Background(_) => panic!("nonsense got through"),
+            _ => None,
+        });
+        assert_eq!(radius, 2.0);
+
+        // A line that never ends drops the connection
+        rogue.write_all(&vec![b' '; MAX_LINE as usize + 1]).unwrap();
+        let left = wait_for(&host_events, |message| match message {
+            Message::Left { peer } => Some(peer),
+            _ => None,
+        });
+        assert_eq!(left, peer);
+    }
+}
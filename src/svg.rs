@@ -0,0 +1,204 @@
+use std::fmt::Write;
+
+use crate::{
+    background::{Background, Pattern},
+    document::{Element, Stroke},
+    export, view,
+};
+
+// Render the document as SVG. `viewport` is the (x, y, width, height) world
+// region to show, scaled to a `size` pixel image, on `background`. A solid color
+// is a rect, patterns and background images are embedded rendered at that size.
+//
+// Eraser strokes become masks: everything drawn before one is wrapped in a group
+// masked by it, so they cut through to whatever is underneath just like on
+// screen
+pub fn to_svg(
+    elements: &[Element],
+    viewport: (f32, f32, f32, f32),
+    (pixel_width, pixel_height): (u32, u32),
+    background: &Background,
+) -> String {
+    let (x, y, width, height) = viewport;
+    let mut masks = String::new();
+    let mut mask_count = 0;
+    let mut body = String::new();
+
+    for element in elements {
+        match element {
+            Element::Stroke(stroke) if stroke.is_eraser => {
+                let Some(path) = stroke_path(stroke, 0x000000) else {
+                    continue;
+                };
+                let id = format!("erase{mask_count}");
+                mask_count += 1;
+                let _ = writeln!(
+                    masks,
+                    r#"    <mask id="{id}" maskUnits="userSpaceOnUse" x="{x}" y="{y}" width="{width}" height="{height}"><rect x="{x}" y="{y}" width="{width}" height="{height}" fill="white"/>{path}</mask>"#
+                );
+                body = format!("<g mask=\"url(#{id})\">\n{body}</g>\n");
+            }
+            Element::Stroke(stroke) => {
+                if let Some(path) = stroke_path(stroke, stroke.color) {
+                    let _ = writeln!(body, "{path}");
+                }
+            }
+            Element::Image {
+                x,
+                y,
+                width,
+                height,
+                image,
+            } => {
+                let Ok(png) =
+                    export::encode_png(image.width, image.height, &export::to_rgba(&image.pixels))
+                else {
+                    continue;
+                };
+                let _ = writeln!(
+                    body,
+                    r#"<image x="{x}" y="{y}" width="{width}" height="{height}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+                    base64(&png)
+                );
+            }
+        }
+    }
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{pixel_width}" height="{pixel_height}" viewBox="{x} {y} {width} {height}">"#
+    );
+    if !masks.is_empty() {
+        let _ = write!(svg, "  <defs>\n{masks}  </defs>\n");
+    }
+    let is_plain = matches!(background.pattern, Pattern::Solid | Pattern::Transparent)
+        && background.image.is_none();
+    if is_plain {
+        if let Some(color) = background.fill_color() {
+            let _ = writeln!(
+                svg,
+                r#"  <rect x="{x}" y="{y}" width="{width}" height="{height}" fill="{}"/>"#,
+                hex_color(color)
+            );
+        }
+    } else if let Some(png) = render_background(background, viewport, (pixel_width, pixel_height)) {
+        let _ = writeln!(
+            svg,
+            r#"  <image x="{x}" y="{y}" width="{width}" height="{height}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+            base64(&png)
+        );
+    }
+    svg.push_str(&body);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Patterns and background images as a PNG of the viewport at the export size
+fn render_background(
+    background: &Background,
+    (x, y, width, _): (f32, f32, f32, f32),
+    (pixel_width, pixel_height): (u32, u32),
+) -> Option<Vec<u8>> {
+    let zoom = pixel_width as f32 / width;
+    let mut pixels = vec![0; (pixel_width * pixel_height) as usize];
+    background.render(
+        &mut pixels,
+        pixel_width,
+        (0, 0, pixel_width, pixel_height),
+        view::to_canvas((x, y), zoom),
+        zoom,
+        false,
+    );
+    export::encode_png(pixel_width, pixel_height, &export::to_rgba(&pixels)).ok()
+}
+
+fn stroke_path(stroke: &Stroke, color: u32) -> Option<String> {
+    let &(first_x, first_y) = stroke.points.first()?;
+
+    let mut data = format!("M{first_x} {first_y}");
+    if stroke.points.len() == 1 {
+        // A zero length segment with round caps renders as a dot
+        data.push_str(" l0 0");
+    }
+    for &(x, y) in &stroke.points[1..] {
+        let _ = write!(data, " L{x} {y}");
+    }
+
+    // The rasterizer stamps circles of `radius` plus one pixel of antialiasing
+    Some(format!(
+        r#"<path d="{data}" fill="none" stroke="{}" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+        hex_color(color),
+        stroke.radius * 2.0 + 1.0
+    ))
+}
+
+fn hex_color(color: u32) -> String {
+    format!("#{:06x}", color & 0x00FFFFFF)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        encoded.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        encoded.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            encoded.push(ALPHABET[(n >> 6) as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(ALPHABET[n as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::image::Image;
+
+    fn svg_on(background: &Background) -> String {
+        to_svg(&[], (0.0, 0.0, 8.0, 4.0), (8, 4), background)
+    }
+
+    #[test]
+    fn solid_background_is_a_rect() {
+        let svg = svg_on(&Background::default());
+        assert!(svg.contains(r##"fill="#0a0a0a""##));
+        assert!(!svg.contains("<image"));
+    }
+
+    #[test]
+    fn patterns_and_images_are_embedded() {
+        let grid = Background {
+            pattern: Pattern::Grid,
+            ..Background::default()
+        };
+        assert!(svg_on(&grid).contains("<image"));
+
+        let image = Background {
+            image: Some(Arc::new(Image {
+                width: 2,
+                height: 2,
+                pixels: vec![0xFFEF4444; 4],
+            })),
+            ..Background::default()
+        };
+        assert!(svg_on(&image).contains("<image"));
+    }
+}
@@ -0,0 +1,321 @@
+// Sparse raster of the document at one zoom level, in canvas pixels (see
+// `view::to_canvas`). The canvas is cut into square tiles that are rendered from
+// the document the first time they come into view, and only get memory once
+// something is actually painted on them. Tiles are a transparent layer, the
+// background is drawn underneath when they are shown
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    blend,
+    document::Element,
+    image::{self, Image},
+    parallel,
+    raster::{self, Paint, Pixels, Target},
+};
+
+pub const TILE_SIZE: i32 = 256;
+const TILE_AREA: usize = (TILE_SIZE * TILE_SIZE) as usize;
+
+// Pixels visited by a run of stamps before it is worth splitting over threads
+const PARALLEL_WORK: usize = 1 << 18;
+
+#[derive(Clone)]
+enum Tile {
+    Blank, // Rendered, but fully transparent
+    Painted(Rc<Vec<u32>>),
+}
+
+// The rendered tiles at one point in time, kept by undo snapshots. Painting
+// copies a tile before changing it, so a snapshot only costs the tiles that
+// changed after it was taken
+#[derive(Clone)]
+pub struct TileSnapshot {
+    zoom: f32,
+    tiles: HashMap<(i32, i32), Tile>,
+}
+
+pub struct TiledCanvas {
+    zoom: f32,
+    tiles: HashMap<(i32, i32), Tile>, // Tiles missing here haven't been rendered yet
+    dirty: HashSet<(i32, i32)>,       // Painted since the last `take_dirty`
+}
+
+impl TiledCanvas {
+    pub fn new(zoom: f32) -> Self {
+        Self {
+            zoom,
+            tiles: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn snapshot(&self) -> TileSnapshot {
+        TileSnapshot {
+            zoom: self.zoom,
+            tiles: self.tiles.clone(),
+        }
+    }
+
+    // Bring back the tiles of a snapshot. Returns false, leaving the canvas as
+    // it is, when they were rendered at another zoom
+    pub fn restore(&mut self, snapshot: TileSnapshot) -> bool {
+        if snapshot.zoom != self.zoom {
+            return false;
+        }
+        self.tiles = snapshot.tiles;
+        self.dirty.clear();
+        true
+    }
+
+    // Render every tile of the canvas `rect` (x, y, width, height) that hasn't
+    // been rendered yet
+    pub fn prepare(&mut self, elements: &[Element], rect: (i32, i32, u32, u32)) {
+        let missing: Vec<(i32, i32)> = tiles_in(rect)
+            .filter(|key| !self.tiles.contains_key(key))
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        let bounds: Vec<_> = elements
+            .iter()
+            .map(|element| raster::bounds(element, self.zoom))
+            .collect();
+        let zoom = self.zoom;
+        let rendered = parallel::map(missing, |key| {
+            (key, render_tile(key, zoom, elements, &bounds))
+        });
+
+        for (key, pixels) in rendered {
+            let tile = match pixels {
+                Some(pixels) => Tile::Painted(Rc::new(pixels)),
+                None => Tile::Blank,
+            };
+            self.tiles.insert(key, tile);
+        }
+    }
+
+    // Forget the tiles overlapping the inclusive canvas box, for changes that
+    // can't be painted on top of what is there. They render again from the
+    // document when next shown
+    pub fn invalidate(&mut self, area: (i32, i32, i32, i32)) {
+        for key in tiles_touched(area) {
+            self.tiles.remove(&key);
+            self.dirty.remove(&key);
+        }
+    }
+
+    // Canvas rects (x, y, width, height) of the tiles painted since the last call
+    pub fn take_dirty(&mut self) -> Vec<(i32, i32, u32, u32)> {
+        self.dirty
+            .drain()
+            .map(|(tile_x, tile_y)| {
+                (
+                    tile_x * TILE_SIZE,
+                    tile_y * TILE_SIZE,
+                    TILE_SIZE as u32,
+                    TILE_SIZE as u32,
+                )
+            })
+            .collect()
+    }
+
+    // Lay the canvas over `rect` (x, y, width, height) of a `target_width` pixels
+    // wide buffer that already holds the background, target pixel (0, 0) being
+    // canvas pixel `origin`. Tiles that haven't been rendered are left out
+    pub fn composite_onto(
+        &self,
+        target: &mut [u32],
+        target_width: u32,
+        (x, y, width, height): (u32, u32, u32, u32),
+        origin: (i32, i32),
+    ) {
+        for row in y..y + height {
+            let canvas_y = row as i32 + origin.1;
+            let tile_y = canvas_y.div_euclid(TILE_SIZE);
+            let tile_row = canvas_y.rem_euclid(TILE_SIZE) as usize;
+
+            let mut column = x;
+            while column < x + width {
+                let canvas_x = column as i32 + origin.0;
+                let tile_x = canvas_x.div_euclid(TILE_SIZE);
+                let tile_column = canvas_x.rem_euclid(TILE_SIZE) as usize;
+                // Up to the end of this tile or of the rect, whichever comes first
+                let span = (TILE_SIZE as usize - tile_column).min((x + width - column) as usize);
+
+                let start = (row * target_width + column) as usize;
+                let destination = &mut target[start..start + span];
+                if let Some(Tile::Painted(pixels)) = self.tiles.get(&(tile_x, tile_y)) {
+                    let source = tile_row * TILE_SIZE as usize + tile_column;
+                    blend::composite(destination, &pixels[source..source + span]);
+                }
+
+                column += span as u32;
+            }
+        }
+    }
+
+    // Run `paint` on every rendered tile overlapping the inclusive canvas box,
+    // with the tile pixels and the canvas position of its top-left corner.
+    // Tiles that haven't been rendered are skipped, they'll pick the change up
+    // from the document when they are
+    fn paint(&mut self, area: (i32, i32, i32, i32), mut paint: impl FnMut(&mut [u32], (i32, i32))) {
+        for (tile_x, tile_y) in tiles_touched(area) {
+            if let Some(tile) = self.tiles.get_mut(&(tile_x, tile_y)) {
+                paint(tile_pixels(tile), (tile_x * TILE_SIZE, tile_y * TILE_SIZE));
+                self.dirty.insert((tile_x, tile_y));
+            }
+        }
+    }
+}
+
+// The stamps of a run that land on one tile
+struct TileStamps<'a> {
+    pixels: &'a mut [u32],
+    origin: (i32, i32), // Canvas position of the tile's top-left corner
+    centers: &'a [(i32, i32)],
+}
+
+// Pixels of a rendered tile ready to be painted on, copied first if an undo
+// snapshot still shares them
+fn tile_pixels(tile: &mut Tile) -> &mut [u32] {
+    if let Tile::Blank = tile {
+        *tile = Tile::Painted(Rc::new(vec![0; TILE_AREA]));
+    }
+    match tile {
+        Tile::Painted(pixels) => Rc::make_mut(pixels).as_mut_slice(),
+        Tile::Blank => unreachable!(),
+    }
+}
+
+// A tile rendered from scratch, `None` when no element reaches into it
+fn render_tile(
+    (tile_x, tile_y): (i32, i32),
+    zoom: f32,
+    elements: &[Element],
+    bounds: &[(i32, i32, i32, i32)],
+) -> Option<Vec<u32>> {
+    let (left, top) = (tile_x * TILE_SIZE, tile_y * TILE_SIZE);
+    let mut pixels: Option<Vec<u32>> = None;
+
+    for (element, &(min_x, min_y, max_x, max_y)) in elements.iter().zip(bounds) {
+        if max_x < left || min_x >= left + TILE_SIZE || max_y < top || min_y >= top + TILE_SIZE {
+            continue;
+        }
+        let pixels = pixels.get_or_insert_with(|| vec![0; TILE_AREA]);
+        raster::draw_element(
+            &mut Pixels {
+                pixels,
+                width: TILE_SIZE as u32,
+                height: TILE_SIZE as u32,
+                origin: (left, top),
+            },
+            zoom,
+            element,
+        );
+    }
+
+    pixels
+}
+
+impl Target for TiledCanvas {
+    fn stamp_circle(&mut self, (x, y): (i32, i32), radius: i32, paint: Paint) {
+        let reach = radius + 1;
+        self.paint(
+            (x - reach, y - reach, x + reach, y + reach),
+            |pixels, (left, top)| {
+                raster::stamp_circle(
+                    pixels,
+                    TILE_SIZE,
+                    TILE_SIZE,
+                    (x - left, y - top),
+                    radius,
+                    paint,
+                )
+            },
+        );
+    }
+
+    // Every tile takes the stamps that land on it in stroke order, so tiles can
+    // be painted independently and in parallel when the run is long enough
+    fn stamp_circles(&mut self, centers: &[(i32, i32)], radius: i32, paint: Paint) {
+        let reach = radius + 1;
+        let mut hits: HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::new();
+        for &(x, y) in centers {
+            for key in tiles_touched((x - reach, y - reach, x + reach, y + reach)) {
+                if self.tiles.contains_key(&key) {
+                    hits.entry(key).or_default().push((x, y));
+                }
+            }
+        }
+
+        let jobs: Vec<TileStamps> = self
+            .tiles
+            .iter_mut()
+            .filter_map(|(key, tile)| {
+                let centers = hits.get(key)?;
+                Some(TileStamps {
+                    pixels: tile_pixels(tile),
+                    origin: (key.0 * TILE_SIZE, key.1 * TILE_SIZE),
+                    centers,
+                })
+            })
+            .collect();
+        self.dirty.extend(hits.keys());
+
+        let stamp = |job: TileStamps| {
+            for &(x, y) in job.centers {
+                raster::stamp_circle(
+                    job.pixels,
+                    TILE_SIZE,
+                    TILE_SIZE,
+                    (x - job.origin.0, y - job.origin.1),
+                    radius,
+                    paint,
+                );
+            }
+        };
+        let side = (2 * reach + 1) as usize;
+        if jobs.len() > 1 && centers.len() * side * side > PARALLEL_WORK {
+            parallel::map(jobs, stamp);
+        } else {
+            jobs.into_iter().for_each(stamp);
+        }
+    }
+
+    fn draw_image(&mut self, image: &Image, (x, y, width, height): (i32, i32, u32, u32)) {
+        self.paint(
+            (x, y, x + width as i32 - 1, y + height as i32 - 1),
+            |pixels, (left, top)| {
+                image::draw_scaled(
+                    image,
+                    (x - left, y - top, width, height),
+                    pixels,
+                    TILE_SIZE as u32,
+                    TILE_SIZE as u32,
+                )
+            },
+        );
+    }
+}
+
+// Keys of the tiles overlapping an inclusive canvas box
+fn tiles_touched(
+    (min_x, min_y, max_x, max_y): (i32, i32, i32, i32),
+) -> impl Iterator<Item = (i32, i32)> {
+    let (min_x, max_x) = (min_x.div_euclid(TILE_SIZE), max_x.div_euclid(TILE_SIZE));
+    let (min_y, max_y) = (min_y.div_euclid(TILE_SIZE), max_y.div_euclid(TILE_SIZE));
+    (min_y..=max_y).flat_map(move |tile_y| (min_x..=max_x).map(move |tile_x| (tile_x, tile_y)))
+}
+
+// Keys of the tiles overlapping a canvas rect (x, y, width, height)
+fn tiles_in((x, y, width, height): (i32, i32, u32, u32)) -> impl Iterator<Item = (i32, i32)> {
+    tiles_touched((x, y, x + width as i32 - 1, y + height as i32 - 1))
+}
@@ -0,0 +1,61 @@
+// Maps the unbounded world the document lives in onto the window
+#[derive(Clone, Copy, PartialEq)]
+pub struct View {
+    pub offset_x: f32, // World coordinates of the top-left window corner
+    pub offset_y: f32,
+    pub zoom: f32, // Screen pixels per world unit
+}
+
+pub const MIN_ZOOM: f32 = 0.1;
+pub const MAX_ZOOM: f32 = 10.0;
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            offset_x: 0.0,
+            offset_y: 0.0,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl View {
+    // The canvas pixel shown in the top-left window corner
+    pub fn origin(self) -> (i32, i32) {
+        to_canvas((self.offset_x, self.offset_y), self.zoom)
+    }
+
+    pub fn to_world(self, (x, y): (i32, i32)) -> (f32, f32) {
+        let (origin_x, origin_y) = self.origin();
+        (
+            (x + origin_x) as f32 / self.zoom,
+            (y + origin_y) as f32 / self.zoom,
+        )
+    }
+
+    // Move the view by a distance given in screen pixels
+    pub fn pan(&mut self, dx: i32, dy: i32) {
+        self.offset_x -= dx as f32 / self.zoom;
+        self.offset_y -= dy as f32 / self.zoom;
+    }
+
+    // Zoom by `factor` keeping the world point under `anchor` (a screen position)
+    // where it is
+    pub fn zoom_at(&mut self, anchor: (i32, i32), factor: f32) {
+        let world = self.to_world(anchor);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset_x = world.0 - anchor.0 as f32 / self.zoom;
+        self.offset_y = world.1 - anchor.1 as f32 / self.zoom;
+    }
+}
+
+// Canvas pixels are world coordinates scaled by the zoom. Everything is
+// rasterized in them, so panning only moves pixels around and never changes them
+pub fn to_canvas((x, y): (f32, f32), zoom: f32) -> (i32, i32) {
+    ((x * zoom).round() as i32, (y * zoom).round() as i32)
+}
+
+// A world space length (brush radius, image size) in canvas pixels
+pub fn to_canvas_length(length: f32, zoom: f32) -> i32 {
+    (length * zoom).round() as i32
+}